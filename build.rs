@@ -0,0 +1,35 @@
+//! Detects the features of the compiler that the macro can make use of.
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rustc-check-cfg=cfg(span_local_file)");
+    println!("cargo:rustc-check-cfg=cfg(nightly_tracking)");
+
+    // e.g. `rustc 1.88.0 (6b00bc388 2025-06-23)` or `rustc 1.90.0-nightly (a84bb95a1 2025-07-09)`
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .and_then(|version| version.split_whitespace().nth(1).map(str::to_owned));
+
+    // `proc_macro::Span::local_file` was stabilized in Rust 1.88
+    let minor_version = version
+        .as_deref()
+        .and_then(|version| version.split('.').nth(1)?.parse::<u32>().ok());
+    if minor_version.is_some_and(|minor_version| minor_version >= 88) {
+        println!("cargo:rustc-cfg=span_local_file");
+    }
+
+    // The `nightly` feature uses unstable APIs, so only takes effect on compilers which allow them. This keeps
+    // `--all-features` building on stable compilers.
+    let is_nightly = version
+        .as_deref()
+        .is_some_and(|version| version.contains("nightly") || version.contains("dev"));
+    if is_nightly && std::env::var_os("CARGO_FEATURE_NIGHTLY").is_some() {
+        println!("cargo:rustc-cfg=nightly_tracking");
+    }
+}
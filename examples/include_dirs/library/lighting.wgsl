@@ -0,0 +1,5 @@
+#import colour.wgsl as Colour
+
+fn light(albedo: vec3<f32>) -> vec4<f32> {
+    return vec4<f32>(Colour::to_linear(albedo), 1.0);
+}
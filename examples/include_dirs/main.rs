@@ -0,0 +1,10 @@
+// Imports are searched for in each of the include directories, relative to the crate root
+#[include_wgsl_oil::include_wgsl_oil(
+    "shader.wgsl",
+    include_dirs("examples/include_dirs/library", "examples/include_dirs/third_party")
+)]
+mod shader {}
+
+fn main() {
+    println!("Shader source: {}", shader::SOURCE);
+}
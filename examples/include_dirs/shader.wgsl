@@ -0,0 +1,6 @@
+#import lighting.wgsl as Lighting
+
+@fragment
+fn main() -> @location(0) vec4<f32> {
+    return Lighting::light(vec3<f32>(0.5));
+}
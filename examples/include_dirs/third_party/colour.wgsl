@@ -0,0 +1,3 @@
+fn to_linear(srgb: vec3<f32>) -> vec3<f32> {
+    return pow(srgb, vec3<f32>(2.2));
+}
@@ -0,0 +1,19 @@
+mod other;
+
+#[include_wgsl_oil::include_wgsl_oil("shader.wgsl")]
+mod shader {}
+
+// Invocations can also be generated by other macros
+macro_rules! include_shader {
+    ($name:ident, $path:literal) => {
+        #[include_wgsl_oil::include_wgsl_oil($path)]
+        mod $name {}
+    };
+}
+include_shader!(macro_shader, "shader.wgsl");
+
+fn main() {
+    assert_eq!(shader::SOURCE, other::shader::SOURCE);
+    assert_eq!(shader::SOURCE, macro_shader::SOURCE);
+    println!("Shader source: {}", shader::SOURCE);
+}
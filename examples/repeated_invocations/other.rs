@@ -0,0 +1,3 @@
+// The same path can be included from more than one file
+#[include_wgsl_oil::include_wgsl_oil("shader.wgsl")]
+pub mod shader {}
@@ -0,0 +1,27 @@
+const MAX_LIGHTS: u32 = #MAX_LIGHTS;
+const BIAS: i32 = #BIAS;
+
+#ifdef WEBGPU
+const MAX_SAMPLES: u32 = 4u;
+#else
+const MAX_SAMPLES: u32 = 16u;
+#endif
+
+#ifdef SHADOWS
+fn shadow_factor() -> f32 {
+    return 0.5;
+}
+#else
+fn shadow_factor() -> f32 {
+    return 1.0;
+}
+#endif
+
+@fragment
+fn main() -> @location(0) vec4<f32> {
+#ifdef __DEBUG
+    return vec4<f32>(1.0, 0.0, 1.0, 1.0);
+#else
+    return vec4<f32>(shadow_factor() * f32(MAX_LIGHTS) + f32(BIAS) / f32(MAX_SAMPLES));
+#endif
+}
@@ -0,0 +1,11 @@
+#[include_wgsl_oil::include_wgsl_oil(
+    "lights.wgsl",
+    defs(MAX_LIGHTS = 16u, SHADOWS = true, BIAS = -2),
+    cfg_defs(WEBGPU = target_arch = "wasm32")
+)]
+mod lights_shader {}
+
+fn main() {
+    println!("Max lights: {}", lights_shader::constants::MAX_LIGHTS::VALUE);
+    println!("Lights source: {}", lights_shader::SOURCE);
+}
@@ -0,0 +1,20 @@
+#[include_wgsl_oil::include_wgsl_oil("uber.wgsl", variants(SHADOWS = [true, false], SAMPLES = [1u, 4u]))]
+mod uber_shader {}
+
+fn main() {
+    // Types which are the same in every variant are shared
+    let _light = uber_shader::types::Light {
+        position: [0.0, 1.0, 0.0],
+        intensity: 2.0,
+    };
+
+    // Types which differ between variants are found in each variant's module
+    let _material = uber_shader::shadows_true_samples_4::types::Material {
+        albedo: [1.0; 4],
+        shadow_bias: 0.1,
+    };
+
+    for variant in uber_shader::Variant::ALL {
+        println!("{:?} source: {}", variant, uber_shader::source(*variant));
+    }
+}
@@ -0,0 +1,26 @@
+@export struct Light {
+    position: array<f32, 3>,
+    intensity: f32,
+}
+
+@export struct Material {
+    albedo: array<f32, 4>,
+#ifdef SHADOWS
+    shadow_bias: f32,
+#endif
+}
+
+const SAMPLES: u32 = #SAMPLES;
+
+@group(0) @binding(0) var<storage, read> light: Light;
+@group(0) @binding(1) var<storage, read> material: Material;
+
+@fragment
+fn main() -> @location(0) vec4<f32> {
+    let albedo = vec4<f32>(material.albedo[0], material.albedo[1], material.albedo[2], material.albedo[3]);
+    var colour = albedo * light.intensity / f32(SAMPLES);
+#ifdef SHADOWS
+    colour = colour * (1.0 - material.shadow_bias);
+#endif
+    return colour;
+}
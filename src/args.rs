@@ -0,0 +1,409 @@
+use std::path::PathBuf;
+
+use naga_oil::compose::ShaderDefValue;
+use quote::ToTokens;
+use syn::{
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+    Token,
+};
+
+use crate::{cfg_defs::CfgDef, config::Config, files};
+
+/// Shader definitions which are provided by this crate and so can't be given by the user.
+const RESERVED_DEFS: &[&str] = &["__DEBUG"];
+
+/// A single shader definition given to the macro, e.g. `MAX_LIGHTS = 16u`.
+pub(crate) struct ShaderDef {
+    pub(crate) name: syn::Ident,
+    pub(crate) value: ShaderDefValue,
+}
+
+impl Parse for ShaderDef {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let name: syn::Ident = input.parse()?;
+
+        // A definition without a value is a flag, in the same way as `#define FOO` in a shader
+        let value = if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            parse_shader_def_value(input)?
+        } else {
+            ShaderDefValue::Bool(true)
+        };
+
+        Ok(Self { name, value })
+    }
+}
+
+/// Parses a `bool`, `i32` or `u32` value, as would be written in WGSL, e.g. `true`, `-2`, `7i` or `16u`.
+pub(crate) fn parse_shader_def_value(input: ParseStream<'_>) -> syn::Result<ShaderDefValue> {
+    let negative = input.peek(Token![-]);
+    if negative {
+        input.parse::<Token![-]>()?;
+    }
+
+    let lit: syn::Lit = input.parse()?;
+    match lit {
+        syn::Lit::Bool(lit) if !negative => Ok(ShaderDefValue::Bool(lit.value)),
+        syn::Lit::Int(lit) => match lit.suffix() {
+            "u" | "u32" => {
+                if negative {
+                    return Err(syn::Error::new(
+                        lit.span(),
+                        "`u32` shader def values cannot be negative",
+                    ));
+                }
+                Ok(ShaderDefValue::UInt(lit.base10_parse::<u32>()?))
+            }
+            "" | "i" | "i32" => {
+                let value = lit.base10_parse::<i64>()?;
+                let value = if negative { -value } else { value };
+                let value = i32::try_from(value).map_err(|_| {
+                    syn::Error::new(lit.span(), "shader def value does not fit in an `i32`")
+                })?;
+                Ok(ShaderDefValue::Int(value))
+            }
+            suffix => Err(syn::Error::new(
+                lit.span(),
+                format!(
+                    "unsupported integer suffix `{}` - expected `u` for a `u32` or `i` for an `i32`",
+                    suffix
+                ),
+            )),
+        },
+        lit => Err(syn::Error::new(
+            lit.span(),
+            "expected a `bool`, `i32` or `u32` shader def value, e.g. `true`, `-2` or `16u`",
+        )),
+    }
+}
+
+/// Errors if a definition name is reserved for use by this crate.
+pub(crate) fn check_def_name_str(name: &str) -> Result<(), String> {
+    if RESERVED_DEFS.contains(&name) {
+        return Err(format!(
+            "`{}` is defined by `include_wgsl_oil` and cannot be overridden",
+            name
+        ));
+    }
+
+    Ok(())
+}
+
+/// Errors if a definition name given to the macro is reserved or has already been given.
+fn check_def_name(name: &syn::Ident, seen: &[&syn::Ident]) -> syn::Result<()> {
+    check_def_name_str(&name.to_string()).map_err(|e| syn::Error::new(name.span(), e))?;
+    if seen.contains(&name) {
+        return Err(syn::Error::new(
+            name.span(),
+            format!("shader def `{}` is defined more than once", name),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Parses a parenthesised, comma separated list of definitions, e.g. `(FOO = 1u, BAR)`, erroring on duplicates.
+fn parse_shader_defs(input: ParseStream<'_>) -> syn::Result<Vec<ShaderDef>> {
+    let content;
+    syn::parenthesized!(content in input);
+    let defs = Punctuated::<ShaderDef, Token![,]>::parse_terminated(&content)?;
+
+    let mut seen = Vec::<&syn::Ident>::new();
+    for def in &defs {
+        check_def_name(&def.name, &seen)?;
+        seen.push(&def.name);
+    }
+
+    Ok(defs.into_iter().collect())
+}
+
+/// Parses the shader definitions given to a parametrized import, e.g. `RADIUS = 5, HORIZONTAL` in
+/// `#import blur.wgsl with (RADIUS = 5, HORIZONTAL)`, erroring on duplicates.
+pub(crate) fn parse_import_params(text: &str) -> Result<Vec<(String, ShaderDefValue)>, String> {
+    let defs =
+        syn::parse::Parser::parse_str(Punctuated::<ShaderDef, Token![,]>::parse_terminated, text)
+            .map_err(|e| format!("could not parse parameters `{}`: {}", text.trim(), e))?;
+
+    let mut seen = Vec::<&syn::Ident>::new();
+    for def in &defs {
+        check_def_name(&def.name, &seen).map_err(|e| e.to_string())?;
+        seen.push(&def.name);
+    }
+
+    Ok(defs
+        .into_iter()
+        .map(|def| (def.name.to_string(), def.value))
+        .collect())
+}
+
+/// A single shader definition which takes a set of values, one per generated variant, e.g. `SAMPLES = [1, 4]`.
+pub(crate) struct VariantAxis {
+    pub(crate) name: syn::Ident,
+    pub(crate) values: Vec<ShaderDefValue>,
+}
+
+impl Parse for VariantAxis {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let name: syn::Ident = input.parse()?;
+        input.parse::<Token![=]>()?;
+
+        let content;
+        let brackets = syn::bracketed!(content in input);
+        let mut values = Vec::new();
+        while !content.is_empty() {
+            let span = content.span();
+            let value = parse_shader_def_value(&content)?;
+
+            if let Some(first) = values.first() {
+                if std::mem::discriminant(first) != std::mem::discriminant(&value) {
+                    return Err(syn::Error::new(
+                        span,
+                        format!("every value of variant `{}` must have the same type", name),
+                    ));
+                }
+            }
+            if values.contains(&value) {
+                return Err(syn::Error::new(
+                    span,
+                    format!("variant `{}` has a duplicated value", name),
+                ));
+            }
+            values.push(value);
+
+            if content.is_empty() {
+                break;
+            }
+            content.parse::<Token![,]>()?;
+        }
+
+        if values.is_empty() {
+            return Err(syn::Error::new(
+                brackets.span.join(),
+                format!("variant `{}` must be given at least one value", name),
+            ));
+        }
+
+        Ok(Self { name, values })
+    }
+}
+
+/// Parses a parenthesised, comma separated list of variant axes, e.g. `(SHADOWS = [true, false], SAMPLES = [1, 4])`.
+fn parse_variant_axes(input: ParseStream<'_>) -> syn::Result<Vec<VariantAxis>> {
+    let content;
+    syn::parenthesized!(content in input);
+    let axes = Punctuated::<VariantAxis, Token![,]>::parse_terminated(&content)?;
+
+    let mut seen = Vec::<&syn::Ident>::new();
+    for axis in &axes {
+        check_def_name(&axis.name, &seen)?;
+
+        // The modules generated for each variant are named in lowercase, so names which only differ in case would clash
+        let lowercase = axis.name.to_string().to_lowercase();
+        if let Some(clashing) = seen
+            .iter()
+            .find(|seen| seen.to_string().to_lowercase() == lowercase)
+        {
+            return Err(syn::Error::new(
+                axis.name.span(),
+                format!(
+                    "variant `{}` only differs in case from variant `{}`, so the modules and keys generated for \
+                    them would clash",
+                    axis.name, clashing
+                ),
+            ));
+        }
+        seen.push(&axis.name);
+    }
+
+    Ok(axes.into_iter().collect())
+}
+
+/// A shader definition which follows a `cfg` predicate in the invoking crate, e.g. `RAYTRACING = feature = "rt"`.
+pub(crate) struct CfgDefArg {
+    pub(crate) name: syn::Ident,
+    pub(crate) predicate: syn::Meta,
+}
+
+impl Parse for CfgDefArg {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let predicate = input.parse()?;
+
+        Ok(Self { name, predicate })
+    }
+}
+
+/// Parses a parenthesised, comma separated list of `cfg` definitions, e.g. `(WEBGPU = target_arch = "wasm32")`.
+fn parse_cfg_defs(input: ParseStream<'_>) -> syn::Result<Vec<CfgDefArg>> {
+    let content;
+    syn::parenthesized!(content in input);
+    let defs = Punctuated::<CfgDefArg, Token![,]>::parse_terminated(&content)?;
+
+    let mut seen = Vec::<&syn::Ident>::new();
+    for def in &defs {
+        check_def_name(&def.name, &seen)?;
+        seen.push(&def.name);
+    }
+
+    Ok(defs.into_iter().collect())
+}
+
+/// Parses a parenthesised, comma separated list of folders, e.g. `("assets/shaders", "third_party/wgsl")`.
+fn parse_include_dirs(input: ParseStream<'_>) -> syn::Result<Vec<syn::LitStr>> {
+    let content;
+    syn::parenthesized!(content in input);
+    let dirs = Punctuated::<syn::LitStr, Token![,]>::parse_terminated(&content)?;
+
+    Ok(dirs.into_iter().collect())
+}
+
+/// The arguments given to the `include_wgsl_oil` attribute, e.g. `("path/to/shader.wgsl", defs(FOO = 1u))`.
+pub(crate) struct MacroArgs {
+    pub(crate) path: syn::LitStr,
+    pub(crate) defs: Vec<ShaderDef>,
+    pub(crate) variants: Vec<VariantAxis>,
+    pub(crate) cfg_defs: Vec<CfgDefArg>,
+    pub(crate) include_dirs: Vec<syn::LitStr>,
+}
+
+impl MacroArgs {
+    /// Applies the arguments given to the macro on top of the crate-wide configuration. Shader definitions given to
+    /// the macro override those with the same name in the configuration, and include directories given to the macro
+    /// replace those in the configuration.
+    pub(crate) fn apply_to(&self, config: &Config) -> syn::Result<Config> {
+        let mut config = config.clone();
+
+        config.defs.extend(
+            self.defs
+                .iter()
+                .map(|def| (def.name.to_string(), def.value)),
+        );
+
+        if !self.include_dirs.is_empty() {
+            config.include_dirs = self.include_dirs()?;
+        }
+
+        Ok(config)
+    }
+
+    /// The definitions which follow `cfg` predicates in the invoking crate, including those added by this crate.
+    pub(crate) fn cfg_defs(&self) -> Vec<CfgDef> {
+        std::iter::once(CfgDef::debug())
+            .chain(
+                self.cfg_defs
+                    .iter()
+                    .map(|def| CfgDef::new(def.name.to_string(), def.predicate.to_token_stream())),
+            )
+            .collect()
+    }
+
+    /// The extra folders to search for imports, in order, as absolute paths. Folders are given relative to the
+    /// root of the invoking crate, and are checked to exist.
+    fn include_dirs(&self) -> syn::Result<Vec<PathBuf>> {
+        let root = files::manifest_dir().map_err(|e| syn::Error::new(self.path.span(), e))?;
+        self.include_dirs
+            .iter()
+            .map(|dir| {
+                let path = root.join(dir.value());
+                if !path.is_dir() {
+                    return Err(syn::Error::new(
+                        dir.span(),
+                        format!("include directory `{}` does not exist", path.display()),
+                    ));
+                }
+                Ok(path)
+            })
+            .collect()
+    }
+}
+
+impl Parse for MacroArgs {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let path = input.parse()?;
+
+        let mut defs = None;
+        let mut variants = None;
+        let mut cfg_defs = None;
+        let mut include_dirs = None;
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+
+            let name: syn::Ident = input.parse()?;
+            let duplicated =
+                || syn::Error::new(name.span(), format!("`{}` was given more than once", name));
+            match name.to_string().as_str() {
+                "defs" if defs.is_some() => return Err(duplicated()),
+                "defs" => defs = Some(parse_shader_defs(input)?),
+                "variants" if variants.is_some() => return Err(duplicated()),
+                "variants" => variants = Some(parse_variant_axes(input)?),
+                "cfg_defs" if cfg_defs.is_some() => return Err(duplicated()),
+                "cfg_defs" => cfg_defs = Some(parse_cfg_defs(input)?),
+                "include_dirs" if include_dirs.is_some() => return Err(duplicated()),
+                "include_dirs" => include_dirs = Some(parse_include_dirs(input)?),
+                _ => {
+                    return Err(syn::Error::new(
+                        name.span(),
+                        format!(
+                            "unknown argument `{}` - expected one of `defs(...)`, `variants(...)`, \
+                            `cfg_defs(...)` or `include_dirs(...)`",
+                            name
+                        ),
+                    ))
+                }
+            }
+        }
+
+        let defs: Vec<ShaderDef> = defs.unwrap_or_default();
+        let variants: Vec<VariantAxis> = variants.unwrap_or_default();
+        let cfg_defs: Vec<CfgDefArg> = cfg_defs.unwrap_or_default();
+
+        // Each kind of definition is checked for duplicates when parsed, so check between kinds
+        let mut seen = defs.iter().map(|def| &def.name).collect::<Vec<_>>();
+        for name in variants
+            .iter()
+            .map(|axis| &axis.name)
+            .chain(cfg_defs.iter().map(|def| &def.name))
+        {
+            check_def_name(name, &seen)?;
+            seen.push(name);
+        }
+
+        Ok(Self {
+            path,
+            defs,
+            variants,
+            cfg_defs,
+            include_dirs: include_dirs.unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(args: &str) -> String {
+        match syn::parse_str::<MacroArgs>(args) {
+            Ok(_) => panic!("`{}` is invalid", args),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn rejects_variants_differing_only_in_case() {
+        assert_eq!(
+            parse_error(r#""shader.wgsl", variants(FOO = [1, 2], foo = [3])"#),
+            "variant `foo` only differs in case from variant `FOO`, so the modules and keys generated for them would \
+            clash"
+        );
+        assert!(
+            syn::parse_str::<MacroArgs>(r#""shader.wgsl", variants(FOO = [1, 2], BAR = [3])"#)
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet};
+
+use naga_oil::compose::ShaderDefValue;
+use proc_macro2::TokenStream;
+use regex::Regex;
+
+use crate::{files::AbsoluteWGSLFilePathBuf, lexer};
+
+/// A shader definition which is defined iff a `cfg` predicate holds in the crate invoking the macro.
+/// Since a proc-macro can't evaluate the `cfg`s of the crate it is expanded in, shaders referencing these
+/// definitions are composed once per combination, with the results hidden behind matching `#[cfg(...)]` attributes.
+pub(crate) struct CfgDef {
+    name: String,
+    predicate: TokenStream,
+}
+
+impl CfgDef {
+    pub(crate) fn new(name: String, predicate: TokenStream) -> Self {
+        Self { name, predicate }
+    }
+
+    /// `__DEBUG`, which follows `debug_assertions` in the crate being built rather than in this proc-macro.
+    pub(crate) fn debug() -> Self {
+        Self {
+            name: "__DEBUG".to_owned(),
+            predicate: quote::quote!(debug_assertions),
+        }
+    }
+
+    /// Checks if a shader's source, with its comments stripped, mentions this definition, and so might need composing
+    /// with and without it.
+    fn is_referenced_by(&self, source: &str) -> bool {
+        let pattern = format!(r"\b{}\b", regex::escape(&self.name));
+        Regex::new(&pattern)
+            .expect("escaped pattern is valid")
+            .is_match(source)
+    }
+}
+
+/// Adds a `#[cfg(...)]` attribute to every item given.
+fn gate_items(items: Vec<syn::Item>, predicate: &TokenStream) -> Vec<syn::Item> {
+    items
+        .into_iter()
+        .map(|item| {
+            syn::parse_quote! {
+                #[cfg(#predicate)]
+                #item
+            }
+        })
+        .collect()
+}
+
+/// The items generated for a combination of definitions, and every file that was read while generating them.
+type Generated = (Vec<syn::Item>, Vec<AbsoluteWGSLFilePathBuf>);
+
+/// Gives every combination of a set of definitions being defined or not. The first combination has every definition
+/// defined.
+fn combinations<'a>(defs: &[&'a CfgDef]) -> Vec<Vec<(&'a CfgDef, bool)>> {
+    (0..(1usize << defs.len()))
+        .map(|combination| {
+            defs.iter()
+                .enumerate()
+                .map(|(i, def)| (*def, combination & (1 << i) == 0))
+                .collect()
+        })
+        .collect()
+}
+
+/// Generates the items for a shader once for every combination of the `cfg` definitions that the shader references,
+/// gating each set of items behind the `cfg` predicate that selects it. `generate` is given the definitions to compose
+/// with, and returns the items generated and every file that was read while generating them.
+///
+/// Which files are read can depend on the definitions, e.g. when a file is only imported within an `#ifdef`, so the
+/// definitions referenced by the files read for each combination are searched for until no more are found.
+pub(crate) fn cfg_gated_items(
+    cfg_defs: &[CfgDef],
+    mut generate: impl FnMut(HashMap<String, ShaderDefValue>) -> Generated,
+) -> Vec<syn::Item> {
+    // Generate with everything defined first, to find the definitions that are used. Definitions which are never
+    // referenced make no difference, so these items stand in for every combination with each referenced definition
+    // defined.
+    let all_defined = cfg_defs
+        .iter()
+        .map(|def| (def.name.clone(), ShaderDefValue::Bool(true)))
+        .collect();
+    let all_defined = generate(all_defined);
+
+    // The results of each combination other than the first, by the names of the definitions defined
+    let mut generated = HashMap::<Vec<String>, Generated>::new();
+    let mut referenced = Vec::<&CfgDef>::new();
+    loop {
+        let files = std::iter::once(&all_defined)
+            .chain(generated.values())
+            .flat_map(|(_, files)| files)
+            .collect::<HashSet<_>>();
+        let sources = files
+            .into_iter()
+            .filter_map(|file| std::fs::read_to_string(&**file).ok())
+            .map(|source| lexer::strip_comments(&source))
+            .collect::<Vec<_>>();
+        let found = cfg_defs
+            .iter()
+            .filter(|def| sources.iter().any(|source| def.is_referenced_by(source)))
+            .collect::<Vec<_>>();
+        if found.len() == referenced.len() {
+            break;
+        }
+        referenced = found;
+
+        for combination in combinations(&referenced).into_iter().skip(1) {
+            let defined = combination
+                .iter()
+                .filter(|(_, defined)| *defined)
+                .map(|(def, _)| def.name.clone())
+                .collect::<Vec<_>>();
+            if generated.contains_key(&defined) {
+                continue;
+            }
+            let shader_defs = defined
+                .iter()
+                .map(|name| (name.clone(), ShaderDefValue::Bool(true)))
+                .collect();
+            generated.insert(defined, generate(shader_defs));
+        }
+    }
+    if referenced.is_empty() {
+        return all_defined.0;
+    }
+
+    let mut items = Vec::new();
+    let mut all_defined_items = Some(all_defined.0);
+    for combination in combinations(&referenced) {
+        let predicates = combination.iter().map(|(def, defined)| {
+            let predicate = &def.predicate;
+            if *defined {
+                quote::quote!(#predicate)
+            } else {
+                quote::quote!(not(#predicate))
+            }
+        });
+        let predicate = quote::quote!(all(#(#predicates),*));
+
+        let combination_items = match all_defined_items.take() {
+            Some(all_defined_items) => all_defined_items,
+            None => {
+                let defined = combination
+                    .iter()
+                    .filter(|(_, defined)| *defined)
+                    .map(|(def, _)| def.name.clone())
+                    .collect::<Vec<_>>();
+                generated
+                    .remove(&defined)
+                    .expect("every combination has been generated")
+                    .0
+            }
+        };
+        items.append(&mut gate_items(combination_items, &predicate));
+    }
+
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::test_shaders;
+
+    #[test]
+    fn ignores_defs_in_comments() {
+        let def = CfgDef::new("B".to_owned(), quote::quote!(feature = "b"));
+        let commented = lexer::strip_comments("// #ifdef B\n#ifdef C\n#endif /* B */\n");
+        assert!(!def.is_referenced_by(&commented));
+        let referenced = lexer::strip_comments("#ifdef B // C\n#endif\n");
+        assert!(def.is_referenced_by(&referenced));
+    }
+
+    #[test]
+    fn finds_defs_referenced_only_when_another_is_undefined() {
+        let cfg_defs = vec![
+            CfgDef::new("A".to_owned(), quote::quote!(feature = "a")),
+            CfgDef::new("B".to_owned(), quote::quote!(feature = "b")),
+        ];
+
+        // Stands in for composing `main.wgsl`, which only imports `uses_b.wgsl` when `A` is undefined
+        let mut calls = Vec::new();
+        let items = cfg_gated_items(&cfg_defs, |shader_defs| {
+            let mut defined = shader_defs.keys().cloned().collect::<Vec<_>>();
+            defined.sort();
+            calls.push(defined);
+
+            let mut files = vec![test_shaders::shader("cfg_defs/main.wgsl")];
+            if !shader_defs.contains_key("A") {
+                files.push(test_shaders::shader("cfg_defs/uses_b.wgsl"));
+            }
+            (
+                vec![syn::parse_quote!(
+                    const SOURCE: &str = "";
+                )],
+                files,
+            )
+        });
+
+        assert!(calls.contains(&vec!["B".to_owned()]));
+        assert!(calls.contains(&Vec::<String>::new()));
+        assert_eq!(items.len(), 4);
+        let predicates = items
+            .iter()
+            .map(|item| quote::quote!(#item).to_string())
+            .collect::<Vec<_>>();
+        assert!(predicates
+            .iter()
+            .any(|item| item.contains("not (feature = \"a\")")
+                && item.contains("not (feature = \"b\")")));
+    }
+
+    #[test]
+    fn composes_once_without_referenced_defs() {
+        let cfg_defs = vec![CfgDef::new("C".to_owned(), quote::quote!(feature = "c"))];
+
+        let mut calls = 0;
+        let items = cfg_gated_items(&cfg_defs, |_| {
+            calls += 1;
+            (
+                vec![syn::parse_quote!(
+                    const SOURCE: &str = "";
+                )],
+                vec![test_shaders::shader("cfg_defs/uses_b.wgsl")],
+            )
+        });
+
+        assert_eq!(calls, 1);
+        assert_eq!(items.len(), 1);
+    }
+}
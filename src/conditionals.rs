@@ -0,0 +1,440 @@
+use std::{cmp::Ordering, collections::HashMap, fmt::Display};
+
+use naga_oil::compose::ShaderDefValue;
+use regex::Regex;
+
+use crate::lexer::{self, Directive};
+
+/// The state of a single `#ifdef`, `#ifndef` or `#if` block.
+struct Block {
+    /// Whether the code containing the block is live.
+    parent_live: bool,
+    /// Whether one of the branches of the block has already been taken.
+    taken: bool,
+    /// Whether the current branch of the block is live.
+    live: bool,
+}
+
+/// Compares a shader def value against a value written in a `#if` directive, giving `None` if they can't be compared.
+fn compare(value: &ShaderDefValue, written: &str) -> Option<Ordering> {
+    match value {
+        ShaderDefValue::Bool(value) => Some(value.cmp(&written.parse::<bool>().ok()?)),
+        ShaderDefValue::Int(value) => {
+            let written = written.trim_end_matches("i32").trim_end_matches('i');
+            Some(value.cmp(&written.parse::<i32>().ok()?))
+        }
+        ShaderDefValue::UInt(value) => {
+            let written = written.trim_end_matches("u32").trim_end_matches('u');
+            Some(value.cmp(&written.parse::<u32>().ok()?))
+        }
+    }
+}
+
+/// Evaluates the condition of a `#ifdef`, `#ifndef` or `#if` directive, giving an error if it can't be evaluated, such
+/// as a comparison against a def which isn't given.
+fn evaluate(
+    kind: &str,
+    args: &str,
+    defs: &HashMap<String, ShaderDefValue>,
+) -> Result<bool, String> {
+    match kind {
+        "ifdef" => Ok(defs.contains_key(args.trim())),
+        "ifndef" => Ok(!defs.contains_key(args.trim())),
+        "if" => {
+            let Some((position, op)) = ["==", "!=", "<=", ">=", "<", ">"]
+                .into_iter()
+                .filter_map(|op| Some((args.find(op)?, op)))
+                .min_by_key(|(position, op)| (*position, std::cmp::Reverse(op.len())))
+            else {
+                return Err(
+                    "expected a shader def compared with a value, e.g. `SAMPLES == 4`".to_owned(),
+                );
+            };
+            let name = args[..position].trim();
+            let written = args[position + op.len()..].trim();
+            let value = defs
+                .get(name)
+                .ok_or_else(|| format!("shader def `{}` is not defined", name))?;
+            let ordering = compare(value, written).ok_or_else(|| {
+                format!(
+                    "`{}` cannot be compared with `{}`, which is `{}`",
+                    written,
+                    name,
+                    display_value(value)
+                )
+            })?;
+            Ok(match op {
+                "==" => ordering.is_eq(),
+                "!=" => ordering.is_ne(),
+                "<=" => ordering.is_le(),
+                ">=" => ordering.is_ge(),
+                "<" => ordering.is_lt(),
+                ">" => ordering.is_gt(),
+                _ => unreachable!("only the operators listed are found"),
+            })
+        }
+        _ => Err("expected `#else`, `#else ifdef`, `#else ifndef` or `#else if`".to_owned()),
+    }
+}
+
+/// A condition of a `#ifdef`, `#ifndef`, `#if` or `#else` directive which couldn't be evaluated.
+#[derive(Debug)]
+pub(crate) struct ConditionError {
+    /// The line of the directive, starting from 1.
+    pub(crate) line: usize,
+    pub(crate) message: String,
+}
+
+impl Display for ConditionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// A `#define` in a shader's source.
+pub(crate) struct Define {
+    pub(crate) name: String,
+    pub(crate) value: ShaderDefValue,
+    /// The line that the `#define` is on, starting from 1.
+    pub(crate) line: usize,
+}
+
+/// Formats a shader def value as it would be written in WGSL.
+pub(crate) fn display_value(value: &ShaderDefValue) -> String {
+    match value {
+        ShaderDefValue::Bool(value) => value.to_string(),
+        ShaderDefValue::Int(value) => value.to_string(),
+        ShaderDefValue::UInt(value) => format!("{}u", value),
+    }
+}
+
+/// Formats a shader def value so that it can be part of an identifier, e.g. `neg_4` for `-4`.
+pub(crate) fn ident_value(value: &ShaderDefValue) -> String {
+    match value {
+        ShaderDefValue::Bool(value) => value.to_string(),
+        ShaderDefValue::Int(value) if *value < 0 => format!("neg_{}", value.unsigned_abs()),
+        ShaderDefValue::Int(value) => value.to_string(),
+        ShaderDefValue::UInt(value) => value.to_string(),
+    }
+}
+
+lazy_static::lazy_static! {
+    /// Matches the name and value given to a `#define`, in the same way as `naga_oil`.
+    static ref DEFINE_REGEX: Regex = Regex::new(r"^\s*([\w|]+)\s*([-\w|]+)?").expect("pattern is valid");
+}
+
+/// Parses the name and value given to a `#define`, e.g. `SAMPLES 4`, exactly as `naga_oil` does.
+fn parse_define(args: &str) -> (String, ShaderDefValue) {
+    let Some(captures) = DEFINE_REGEX.captures(args) else {
+        return (args.trim().to_owned(), ShaderDefValue::Bool(true));
+    };
+    let name = captures.get(1).expect("name is captured").as_str();
+    let value = captures.get(2).map_or(ShaderDefValue::Bool(true), |value| {
+        define_value(value.as_str())
+    });
+    (name.to_owned(), value)
+}
+
+/// Parses the value given to a `#define` as `naga_oil` does, which tries a `u32`, then an `i32`, then a `bool`, and
+/// gives `false` for anything else, including suffixed numbers such as `7u`.
+fn define_value(written: &str) -> ShaderDefValue {
+    if let Ok(value) = written.parse::<u32>() {
+        ShaderDefValue::UInt(value)
+    } else if let Ok(value) = written.parse::<i32>() {
+        ShaderDefValue::Int(value)
+    } else if let Ok(value) = written.parse::<bool>() {
+        ShaderDefValue::Bool(value)
+    } else {
+        ShaderDefValue::Bool(false)
+    }
+}
+
+/// Finds the directives in a shader's source which are live with the given shader defs, i.e. which aren't within a
+/// branch of an `#ifdef`, `#ifndef`, `#if` or `#else` which isn't taken. The conditional directives themselves are excluded.
+/// Live `#define`s are applied to the conditions which follow them. Gives an error for the first condition which
+/// can't be evaluated.
+pub(crate) fn live_directives<'a>(
+    source: &'a str,
+    defs: &HashMap<String, ShaderDefValue>,
+) -> Result<Vec<Directive<'a>>, ConditionError> {
+    let mut defs = defs.clone();
+    let mut blocks = Vec::<Block>::new();
+    let mut live_directives = Vec::new();
+
+    for directive in lexer::directives(source) {
+        let live = blocks.last().is_none_or(|block| block.live);
+        let (name, args, start) = match &directive {
+            Directive::Other { name, args, span } => (*name, *args, span.start),
+            _ => {
+                if live {
+                    live_directives.push(directive);
+                }
+                continue;
+            }
+        };
+        let condition_error = |message: String| ConditionError {
+            line: source[..start].matches('\n').count() + 1,
+            message: format!("cannot evaluate `#{} {}`: {}", name, args, message),
+        };
+
+        match name {
+            "ifdef" | "ifndef" | "if" => {
+                let condition = live && evaluate(name, args, &defs).map_err(condition_error)?;
+                blocks.push(Block {
+                    parent_live: live,
+                    taken: condition,
+                    live: condition,
+                });
+            }
+            "else" => {
+                let Some(block) = blocks.last_mut() else {
+                    // Unbalanced, so leave `naga_oil` to report it
+                    continue;
+                };
+                let args = args.trim();
+                let (kind, condition_args) =
+                    args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                let condition = block.parent_live
+                    && !block.taken
+                    && (args.is_empty()
+                        || evaluate(kind, condition_args, &defs).map_err(condition_error)?);
+                block.taken |= condition;
+                block.live = condition;
+            }
+            "endif" => {
+                blocks.pop();
+            }
+            "define" if live => {
+                let (def, value) = parse_define(args);
+                defs.insert(def, value);
+                live_directives.push(directive);
+            }
+            _ => {
+                if live {
+                    live_directives.push(directive);
+                }
+            }
+        }
+    }
+
+    Ok(live_directives)
+}
+
+/// Finds the `#define`s in a shader's source which are live with the given shader defs.
+pub(crate) fn live_defines(
+    source: &str,
+    defs: &HashMap<String, ShaderDefValue>,
+) -> Result<Vec<Define>, ConditionError> {
+    Ok(live_directives(source, defs)?
+        .into_iter()
+        .filter_map(|directive| match directive {
+            Directive::Other {
+                name: "define",
+                args,
+                span,
+            } => {
+                let (name, value) = parse_define(args);
+                Some(Define {
+                    name,
+                    value,
+                    line: source[..span.start].matches('\n').count() + 1,
+                })
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defs(defs: &[(&str, ShaderDefValue)]) -> HashMap<String, ShaderDefValue> {
+        defs.iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect()
+    }
+
+    /// The arguments of every live `#define` in a source.
+    fn live_defined(source: &str, shader_defs: &HashMap<String, ShaderDefValue>) -> Vec<String> {
+        live_defines(source, shader_defs)
+            .expect("conditions can be evaluated")
+            .into_iter()
+            .map(|define| define.name)
+            .collect()
+    }
+
+    #[test]
+    fn formats_values_for_identifiers() {
+        assert_eq!(ident_value(&ShaderDefValue::Bool(false)), "false");
+        assert_eq!(ident_value(&ShaderDefValue::Int(-3)), "neg_3");
+        assert_eq!(ident_value(&ShaderDefValue::Int(3)), "3");
+        assert_eq!(ident_value(&ShaderDefValue::UInt(3)), "3");
+    }
+
+    #[test]
+    fn compares_values_of_the_same_type() {
+        assert_eq!(compare(&ShaderDefValue::Int(4), "4"), Some(Ordering::Equal));
+        assert_eq!(
+            compare(&ShaderDefValue::Int(-2), "3i"),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            compare(&ShaderDefValue::UInt(5), "4u"),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            compare(&ShaderDefValue::Bool(true), "true"),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(compare(&ShaderDefValue::Bool(true), "1"), None);
+        assert_eq!(compare(&ShaderDefValue::UInt(1), "-1"), None);
+    }
+
+    #[test]
+    fn parses_defines() {
+        assert_eq!(
+            parse_define("SHADOWS"),
+            ("SHADOWS".to_owned(), ShaderDefValue::Bool(true))
+        );
+        assert_eq!(
+            parse_define("SAMPLES 4"),
+            ("SAMPLES".to_owned(), ShaderDefValue::UInt(4))
+        );
+        assert_eq!(
+            parse_define(" OFFSET  -3 "),
+            ("OFFSET".to_owned(), ShaderDefValue::Int(-3))
+        );
+        assert_eq!(
+            parse_define("MASK 7u"),
+            ("MASK".to_owned(), ShaderDefValue::Bool(false))
+        );
+        assert_eq!(
+            parse_define("FAST false"),
+            ("FAST".to_owned(), ShaderDefValue::Bool(false))
+        );
+    }
+
+    #[test]
+    fn parses_defines_as_naga_oil_does() {
+        let preprocessor = naga_oil::compose::preprocess::Preprocessor::default();
+        for args in [
+            "N",
+            "N 7",
+            "N 7u",
+            "N -7",
+            "N -7i",
+            "N +7",
+            "N 4294967295",
+            "N -2147483649",
+            "N true",
+            "N 1.5",
+            "N 0x10",
+        ] {
+            let source = format!("#define {}\n", args);
+            let Ok(metadata) = preprocessor.get_preprocessor_metadata(&source, true) else {
+                panic!("`{}` is read by `naga_oil`", source);
+            };
+            let (name, value) = parse_define(args);
+            assert_eq!(
+                metadata.defines.get(&name),
+                Some(&value),
+                "`{}` is read differently",
+                args
+            );
+        }
+    }
+
+    #[test]
+    fn takes_the_first_live_branch() {
+        let source = "\
+#ifdef A
+#define IN_A
+#else ifdef B
+#define IN_B
+#else
+#define IN_ELSE
+#endif
+";
+        let a = defs(&[
+            ("A", ShaderDefValue::Bool(true)),
+            ("B", ShaderDefValue::Bool(true)),
+        ]);
+        assert_eq!(live_defined(source, &a), ["IN_A"]);
+        let b = defs(&[("B", ShaderDefValue::Bool(true))]);
+        assert_eq!(live_defined(source, &b), ["IN_B"]);
+        assert_eq!(live_defined(source, &HashMap::new()), ["IN_ELSE"]);
+    }
+
+    #[test]
+    fn nests_blocks() {
+        let source = "\
+#if SAMPLES >= 4
+#ifndef FAST
+#define SLOW_MANY
+#endif
+#else if SAMPLES == 2
+#define TWO
+#endif
+#define ALWAYS
+";
+        let many = defs(&[("SAMPLES", ShaderDefValue::Int(8))]);
+        assert_eq!(live_defined(source, &many), ["SLOW_MANY", "ALWAYS"]);
+        let fast = defs(&[
+            ("SAMPLES", ShaderDefValue::Int(8)),
+            ("FAST", ShaderDefValue::Bool(true)),
+        ]);
+        assert_eq!(live_defined(source, &fast), ["ALWAYS"]);
+        let two = defs(&[("SAMPLES", ShaderDefValue::Int(2))]);
+        assert_eq!(live_defined(source, &two), ["TWO", "ALWAYS"]);
+    }
+
+    #[test]
+    fn applies_defines_to_later_conditions() {
+        let source = "#define SAMPLES 4\n#if SAMPLES == 4\n#import four.wgsl\n#endif\n";
+        let directives =
+            live_directives(source, &HashMap::new()).expect("conditions can be evaluated");
+        assert!(matches!(
+            directives.as_slice(),
+            [
+                Directive::Other { name: "define", .. },
+                Directive::Import {
+                    path: "four.wgsl",
+                    ..
+                }
+            ]
+        ));
+    }
+
+    #[test]
+    fn skips_conditions_in_dead_branches() {
+        let source = "#ifdef A\n#if MISSING == 1\n#endif\n#endif\n";
+        assert!(live_directives(source, &HashMap::new()).is_ok());
+    }
+
+    #[test]
+    fn reports_conditions_which_cant_be_evaluated() {
+        let error = |source: &str| {
+            let samples = defs(&[("SAMPLES", ShaderDefValue::Int(4))]);
+            let error =
+                live_directives(source, &samples).expect_err("condition can't be evaluated");
+            error.to_string()
+        };
+        assert_eq!(
+            error("fn main() {}\n#if MISSING == 1\n#endif\n"),
+            "line 2: cannot evaluate `#if MISSING == 1`: shader def `MISSING` is not defined"
+        );
+        assert_eq!(
+            error("#if SAMPLES == four\n#endif\n"),
+            "line 1: cannot evaluate `#if SAMPLES == four`: `four` cannot be compared with `SAMPLES`, which is `4`"
+        );
+        assert_eq!(
+            error("#if SAMPLES\n#endif\n"),
+            "line 1: cannot evaluate `#if SAMPLES`: expected a shader def compared with a value, e.g. `SAMPLES == 4`"
+        );
+        assert_eq!(
+            error("#ifdef A\n#else when B\n#endif\n"),
+            "line 2: cannot evaluate `#else when B`: expected `#else`, `#else ifdef`, `#else ifndef` or `#else if`"
+        );
+    }
+}
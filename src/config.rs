@@ -0,0 +1,393 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use naga::valid::Capabilities;
+use naga_oil::compose::ShaderDefValue;
+use syn::parse::Parser;
+
+use crate::{
+    args::{check_def_name_str, parse_shader_def_value},
+    files, prelude,
+};
+
+/// The name of the file that configuration can be given in, as an alternative to the crate's `Cargo.toml`.
+const CONFIG_FILE_NAME: &str = "wgsl-oil.toml";
+
+/// The section of a crate's `Cargo.toml` that configuration can be given in.
+const MANIFEST_SECTION: [&str; 3] = ["package", "metadata", "include-wgsl-oil"];
+
+/// Settings that apply to every invocation of the macro within a crate.
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    /// The extra folders to search for imports, in order, as absolute paths.
+    pub(crate) include_dirs: Vec<PathBuf>,
+    /// Shader definitions given to every shader.
+    pub(crate) defs: HashMap<String, ShaderDefValue>,
+    /// The capabilities that shaders are validated against.
+    pub(crate) capabilities: Capabilities,
+    /// Whether the generated source strings should be minified.
+    pub(crate) minify: bool,
+    /// Whether to generate `glam` types.
+    pub(crate) gen_glam: bool,
+    /// Whether to derive `encase::ShaderType` for exported structs.
+    pub(crate) gen_encase: bool,
+    /// Whether to generate `naga` types.
+    pub(crate) gen_naga: bool,
+    /// The imports added to every shader, each written as it would follow `#import`, e.g. `utils.wgsl::{my_fn}`.
+    pub(crate) prelude: Vec<String>,
+    /// The folder that other crates can import this crate's shaders from, as an absolute path.
+    pub(crate) shader_dir: Option<PathBuf>,
+    /// The files that the configuration was read from, which should trigger recompilation when changed.
+    pub(crate) files: Vec<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            include_dirs: Vec::new(),
+            defs: HashMap::new(),
+            capabilities: Capabilities::all(),
+            minify: cfg!(feature = "minify"),
+            gen_glam: cfg!(feature = "glam"),
+            gen_encase: cfg!(feature = "encase"),
+            gen_naga: cfg!(feature = "naga"),
+            prelude: Vec::new(),
+            shader_dir: None,
+            files: Vec::new(),
+        }
+    }
+}
+
+/// A crate's configuration, along with the modification times of the files it was read from when it was read.
+type CachedConfig = (Vec<Option<SystemTime>>, Result<Arc<Config>, Vec<String>>);
+
+lazy_static::lazy_static! {
+    /// The configuration of each crate that has invoked the macro in this process, by the crate's root folder.
+    static ref CONFIG_CACHE: Mutex<HashMap<PathBuf, CachedConfig>> = Mutex::new(HashMap::new());
+}
+
+pub(crate) fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn read_toml(path: &Path) -> Result<toml::Table, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("could not read `{}`: {}", path.display(), e))?;
+    contents
+        .parse::<toml::Table>()
+        .map_err(|e| format!("could not parse `{}`: {}", path.display(), e))
+}
+
+impl Config {
+    /// Gets the configuration for the crate being compiled, from the `[package.metadata.include-wgsl-oil]` section of
+    /// its `Cargo.toml`, or from a `wgsl-oil.toml` file next to it. Each crate's configuration is read once per process,
+    /// and is only read again if one of the files changes.
+    pub(crate) fn load() -> Result<Arc<Self>, Vec<String>> {
+        let root = files::manifest_dir().map_err(|e| vec![e])?;
+        Self::load_for(root)
+    }
+
+    /// Gets the configuration for the crate with the given root folder, in the same way as [`Config::load`].
+    pub(crate) fn load_for(root: PathBuf) -> Result<Arc<Self>, Vec<String>> {
+        let modified = vec![
+            modified_time(&root.join("Cargo.toml")),
+            modified_time(&root.join(CONFIG_FILE_NAME)),
+        ];
+
+        let mut cache = CONFIG_CACHE
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some((cached_modified, config)) = cache.get(&root) {
+            if *cached_modified == modified {
+                return config.clone();
+            }
+        }
+
+        let config = Self::read(&root).map(Arc::new);
+        cache.insert(root, (modified, config.clone()));
+        config
+    }
+
+    fn read(root: &Path) -> Result<Self, Vec<String>> {
+        let manifest_path = root.join("Cargo.toml");
+        let config_path = root.join(CONFIG_FILE_NAME);
+
+        let manifest = read_toml(&manifest_path).map_err(|e| vec![e])?;
+        let manifest_section = MANIFEST_SECTION
+            .iter()
+            .try_fold(&manifest, |table, key| table.get(*key)?.as_table());
+
+        let config_file = if config_path.is_file() {
+            Some(read_toml(&config_path).map_err(|e| vec![e])?)
+        } else {
+            None
+        };
+
+        let mut config = match (manifest_section, &config_file) {
+            (None, None) => Self::default(),
+            (Some(table), None) => Self::from_table(
+                table,
+                root,
+                &format!(
+                    "`[{}]` in `{}`",
+                    MANIFEST_SECTION.join("."),
+                    manifest_path.display()
+                ),
+            )?,
+            (None, Some(table)) => {
+                Self::from_table(table, root, &format!("`{}`", config_path.display()))?
+            }
+            (Some(_), Some(_)) => {
+                return Err(vec![format!(
+                    "`include_wgsl_oil` configuration was found in both `[{}]` in `{}` and in `{}` \
+                    - only one may be given",
+                    MANIFEST_SECTION.join("."),
+                    manifest_path.display(),
+                    config_path.display()
+                )])
+            }
+        };
+
+        config.files.push(manifest_path);
+        if config_file.is_some() {
+            config.files.push(config_path);
+        }
+
+        Ok(config)
+    }
+
+    /// Reads the configuration given in a TOML table, reporting every problem found.
+    fn from_table(table: &toml::Table, root: &Path, origin: &str) -> Result<Self, Vec<String>> {
+        let mut config = Self::default();
+        let mut errors = Vec::new();
+        let mut error = |message: String| errors.push(format!("in {}: {}", origin, message));
+
+        for (key, value) in table {
+            match key.as_str() {
+                "include_dirs" => match value.as_array() {
+                    Some(dirs) => {
+                        for dir in dirs {
+                            let Some(dir) = dir.as_str() else {
+                                error(format!(
+                                    "`include_dirs` should contain strings, found `{}`",
+                                    dir
+                                ));
+                                continue;
+                            };
+                            let path = root.join(dir);
+                            if !path.is_dir() {
+                                error(format!(
+                                    "include directory `{}` does not exist",
+                                    path.display()
+                                ));
+                            }
+                            config.include_dirs.push(path);
+                        }
+                    }
+                    None => error("`include_dirs` should be an array of folder paths".to_owned()),
+                },
+                "defs" => match value.as_table() {
+                    Some(defs) => {
+                        for (name, value) in defs {
+                            if let Err(e) = check_def_name_str(name) {
+                                error(e);
+                                continue;
+                            }
+                            match Self::def_value(value) {
+                                Some(value) => {
+                                    config.defs.insert(name.clone(), value);
+                                }
+                                None => error(format!(
+                                    "shader def `{}` should be a `bool`, an integer, or a string \
+                                    containing a WGSL literal such as `\"16u\"`, found `{}`",
+                                    name, value
+                                )),
+                            }
+                        }
+                    }
+                    None => error("`defs` should be a table of shader defs".to_owned()),
+                },
+                "capabilities" => match value.as_array() {
+                    Some(names) => {
+                        config.capabilities = Capabilities::empty();
+                        for name in names {
+                            match name.as_str().and_then(Capabilities::from_name) {
+                                Some(capability) => config.capabilities |= capability,
+                                None => error(format!(
+                                    "unknown capability `{}` - expected one of {}",
+                                    name,
+                                    Capabilities::all()
+                                        .iter_names()
+                                        .map(|(name, _)| format!("`{}`", name))
+                                        .collect::<Vec<_>>()
+                                        .join(", ")
+                                )),
+                            }
+                        }
+                    }
+                    None => {
+                        error("`capabilities` should be an array of capability names".to_owned())
+                    }
+                },
+                "minify" => match value.as_bool() {
+                    Some(true) if !cfg!(feature = "minify") => error(
+                        "`minify = true` requires the `minify` feature of `include-wgsl-oil`"
+                            .to_owned(),
+                    ),
+                    Some(minify) => config.minify = minify,
+                    None => error("`minify` should be a `bool`".to_owned()),
+                },
+                "prelude" => match value.as_array() {
+                    Some(imports) => {
+                        for import in imports {
+                            let Some(import) = import.as_str() else {
+                                error(format!(
+                                    "`prelude` should contain strings, found `{}`",
+                                    import
+                                ));
+                                continue;
+                            };
+                            match prelude::check_import(import) {
+                                Ok(()) => config.prelude.push(import.trim().to_owned()),
+                                Err(e) => error(e),
+                            }
+                        }
+                    }
+                    None => error("`prelude` should be an array of imports".to_owned()),
+                },
+                "shader_dir" => match value.as_str() {
+                    Some(dir) => {
+                        let path = root.join(dir);
+                        if !path.is_dir() {
+                            error(format!(
+                                "shader directory `{}` does not exist",
+                                path.display()
+                            ));
+                        }
+                        config.shader_dir = Some(path);
+                    }
+                    None => error("`shader_dir` should be a folder path".to_owned()),
+                },
+                "generate" => match value.as_table() {
+                    Some(generate) => {
+                        for (key, value) in generate {
+                            let target = match key.as_str() {
+                                "glam" => &mut config.gen_glam,
+                                "encase" => &mut config.gen_encase,
+                                "naga" => &mut config.gen_naga,
+                                _ => {
+                                    error(format!(
+                                        "unknown key `generate.{}` - expected one of `glam`, `encase` or `naga`",
+                                        key
+                                    ));
+                                    continue;
+                                }
+                            };
+                            match value.as_bool() {
+                                Some(value) => *target = value,
+                                None => error(format!("`generate.{}` should be a `bool`", key)),
+                            }
+                        }
+                    }
+                    None => error("`generate` should be a table of `bool`s".to_owned()),
+                },
+                _ => error(format!(
+                    "unknown key `{}` - expected one of `include_dirs`, `defs`, `capabilities`, \
+                    `minify`, `prelude`, `shader_dir` or `generate`",
+                    key
+                )),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn def_value(value: &toml::Value) -> Option<ShaderDefValue> {
+        match value {
+            toml::Value::Boolean(value) => Some(ShaderDefValue::Bool(*value)),
+            toml::Value::Integer(value) => Some(ShaderDefValue::Int(i32::try_from(*value).ok()?)),
+            toml::Value::String(value) => parse_shader_def_value.parse_str(value).ok(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::test_shaders;
+
+    fn from_toml(toml: &str) -> Result<Config, Vec<String>> {
+        let table = toml.parse::<toml::Table>().expect("valid TOML");
+        Config::from_table(&table, &test_shaders::folder(), "`wgsl-oil.toml`")
+    }
+
+    #[test]
+    fn reads_every_setting() {
+        let config = from_toml(
+            r#"
+            include_dirs = ["prelude"]
+            defs = { SAMPLES = "4u", SHADOWS = true, BIAS = -2 }
+            capabilities = ["FLOAT64"]
+            prelude = ["math.wgsl::{square}"]
+            generate = { naga = true }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.include_dirs,
+            [test_shaders::folder().join("prelude")]
+        );
+        assert_eq!(config.defs["SAMPLES"], ShaderDefValue::UInt(4));
+        assert_eq!(config.defs["SHADOWS"], ShaderDefValue::Bool(true));
+        assert_eq!(config.defs["BIAS"], ShaderDefValue::Int(-2));
+        assert_eq!(config.capabilities, Capabilities::FLOAT64);
+        assert_eq!(config.prelude, ["math.wgsl::{square}"]);
+        assert!(config.gen_naga);
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let errors = from_toml(
+            r#"
+            defs = { SAMPLES = 1.5 }
+            shaders = "shaders"
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                "in `wgsl-oil.toml`: shader def `SAMPLES` should be a `bool`, an integer, or a string containing a \
+                WGSL literal such as `\"16u\"`, found `1.5`",
+                "in `wgsl-oil.toml`: unknown key `shaders` - expected one of `include_dirs`, `defs`, \
+                `capabilities`, `minify`, `prelude`, `shader_dir` or `generate`",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_configuration_given_twice() {
+        let root = test_shaders::folder().join("config").join("both");
+        let errors = Config::read(&root).unwrap_err();
+        assert_eq!(
+            errors,
+            [format!(
+                "`include_wgsl_oil` configuration was found in both `[package.metadata.include-wgsl-oil]` in `{}` \
+                and in `{}` - only one may be given",
+                root.join("Cargo.toml").display(),
+                root.join("wgsl-oil.toml").display()
+            )]
+        );
+    }
+}
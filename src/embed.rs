@@ -0,0 +1,422 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use naga_oil::compose::ShaderDefValue;
+
+use crate::{conditionals, files::SearchPaths, lexer::Directive, module::Module};
+
+/// The scalar type of the elements of an embedded array.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scalar {
+    U32,
+    I32,
+    F32,
+}
+
+impl Scalar {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "u32" | "u" => Some(Self::U32),
+            "i32" | "i" => Some(Self::I32),
+            "f32" | "f" => Some(Self::F32),
+            _ => None,
+        }
+    }
+
+    /// Parses a value written as text, e.g. in a CSV file, as a WGSL literal of this type.
+    fn parse_text(self, text: &str) -> Option<String> {
+        match self {
+            Self::U32 => text.parse::<u32>().ok().map(|value| format!("{}u", value)),
+            Self::I32 => text.parse::<i32>().ok().map(Self::i32_literal),
+            Self::F32 => text.parse::<f32>().ok().and_then(Self::f32_literal),
+        }
+    }
+
+    /// Reads a little-endian value as a WGSL literal of this type.
+    fn parse_bytes(self, bytes: [u8; 4]) -> Option<String> {
+        match self {
+            Self::U32 => Some(format!("{}u", u32::from_le_bytes(bytes))),
+            Self::I32 => Some(Self::i32_literal(i32::from_le_bytes(bytes))),
+            Self::F32 => Self::f32_literal(f32::from_le_bytes(bytes)),
+        }
+    }
+
+    /// WGSL negates literals rather than reading a sign as part of them, so `-2147483648i` is out of range and the
+    /// smallest value has to be written as an expression.
+    fn i32_literal(value: i32) -> String {
+        if value == i32::MIN {
+            format!("({}i - 1i)", i32::MIN + 1)
+        } else {
+            format!("{}i", value)
+        }
+    }
+
+    /// WGSL has no literals for infinities or NaNs.
+    fn f32_literal(value: f32) -> Option<String> {
+        value.is_finite().then(|| format!("{:?}f", value))
+    }
+}
+
+/// The type of the elements of an embedded array, e.g. `u32` or `vec2<f32>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ElementType {
+    scalar: Scalar,
+    /// The number of components, which is 1 for scalars.
+    components: usize,
+}
+
+impl ElementType {
+    /// Parses a scalar or vector type, e.g. `f32`, `vec3<u32>` or `vec4f`.
+    fn parse(written: &str) -> Option<Self> {
+        let written = written
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>();
+        if let Some(scalar) = Scalar::from_name(&written).filter(|_| written.len() == 3) {
+            return Some(Self {
+                scalar,
+                components: 1,
+            });
+        }
+
+        let rest = written.strip_prefix("vec")?;
+        let components = match rest.chars().next()? {
+            '2' => 2,
+            '3' => 3,
+            '4' => 4,
+            _ => return None,
+        };
+        let scalar = &rest[1..];
+        let scalar = scalar
+            .strip_prefix('<')
+            .and_then(|scalar| scalar.strip_suffix('>'))
+            .unwrap_or(scalar);
+        Some(Self {
+            scalar: Scalar::from_name(scalar)?,
+            components,
+        })
+    }
+
+    /// Writes an element with the given components.
+    fn literal(&self, components: &[String]) -> String {
+        if self.components == 1 {
+            return components[0].clone();
+        }
+        format!("{}({})", self, components.join(", "))
+    }
+}
+
+impl std::fmt::Display for ElementType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scalar = match self.scalar {
+            Scalar::U32 => "u32",
+            Scalar::I32 => "i32",
+            Scalar::F32 => "f32",
+        };
+        if self.components == 1 {
+            write!(f, "{}", scalar)
+        } else {
+            write!(f, "vec{}<{}>", self.components, scalar)
+        }
+    }
+}
+
+/// A parsed `#embed` directive, e.g. `#embed "curve.csv" as CURVE: array<vec2<f32>>`.
+struct Embed<'a> {
+    path: &'a str,
+    name: &'a str,
+    element: ElementType,
+    /// The length given in the array type, if any.
+    len: Option<usize>,
+}
+
+impl<'a> Embed<'a> {
+    fn parse(args: &'a str) -> Result<Self, String> {
+        let malformed = || {
+            format!(
+                "`#embed {}` should be of the form `#embed \"path/to/data.bin\" as NAME: array<u32>`",
+                args
+            )
+        };
+
+        let rest = args.strip_prefix('"').ok_or_else(malformed)?;
+        let (path, rest) = rest.split_once('"').ok_or_else(malformed)?;
+        let rest = rest.trim_start().strip_prefix("as").ok_or_else(malformed)?;
+        let (name, ty) = rest.split_once(':').ok_or_else(malformed)?;
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(malformed());
+        }
+
+        let ty = ty.trim();
+        let ty = ty
+            .strip_prefix("array")
+            .map(str::trim_start)
+            .and_then(|ty| ty.strip_prefix('<'))
+            .and_then(|ty| ty.strip_suffix('>'))
+            .ok_or_else(|| {
+                format!(
+                    "embedded data can only be an `array`, but `{}` is given the type `{}`",
+                    name, ty
+                )
+            })?;
+        let (element, len) = match ty.rsplit_once(',') {
+            Some((element, len)) => {
+                let len = len
+                    .trim()
+                    .trim_end_matches('u')
+                    .parse::<usize>()
+                    .map_err(|_| {
+                        format!("`{}` is not a valid length for embedded data", len.trim())
+                    })?;
+                (element, Some(len))
+            }
+            None => (ty, None),
+        };
+        let element = ElementType::parse(element).ok_or_else(|| {
+            format!(
+                "embedded data can only be an array of `u32`, `i32` or `f32` scalars or vectors, \
+                but `{}` is given elements of type `{}`",
+                name,
+                element.trim()
+            )
+        })?;
+
+        Ok(Self {
+            path,
+            name,
+            element,
+            len,
+        })
+    }
+
+    /// Reads the scalar values in a data file. Files with a `.csv` or `.txt` extension contain values separated by commas
+    /// or whitespace, and any other file contains little-endian binary values.
+    fn read_values(&self, path: &Path) -> Result<Vec<String>, String> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| format!("could not read `{}`: {}", path.display(), e))?;
+        let is_text = path
+            .extension()
+            .is_some_and(|extension| extension == "csv" || extension == "txt");
+
+        if is_text {
+            let text = String::from_utf8(bytes)
+                .map_err(|_| format!("`{}` is not valid UTF-8", path.display()))?;
+            return text
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|value| !value.is_empty())
+                .map(|value| {
+                    self.element.scalar.parse_text(value).ok_or_else(|| {
+                        format!(
+                            "`{}` in `{}` is not a valid `{}`",
+                            value,
+                            path.display(),
+                            ElementType {
+                                scalar: self.element.scalar,
+                                components: 1,
+                            }
+                        )
+                    })
+                })
+                .collect();
+        }
+
+        if bytes.len() % 4 != 0 {
+            return Err(format!(
+                "`{}` is {} bytes long, which is not a whole number of 4 byte values",
+                path.display(),
+                bytes.len()
+            ));
+        }
+        bytes
+            .chunks_exact(4)
+            .map(|chunk| {
+                let value = chunk.try_into().expect("chunks are 4 bytes long");
+                self.element.scalar.parse_bytes(value).ok_or_else(|| {
+                    format!(
+                        "`{}` contains a value which is infinite or NaN, which WGSL cannot represent",
+                        path.display()
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Writes the embedded data as a WGSL `const` array, on a single line so that the lines of the source are unchanged.
+    fn to_const(&self, values: &[String]) -> Result<String, String> {
+        if values.is_empty() {
+            return Err(format!("the data embedded as `{}` is empty", self.name));
+        }
+        if values.len() % self.element.components != 0 {
+            return Err(format!(
+                "the data embedded as `{}` has {} values, which is not a whole number of `{}`s",
+                self.name,
+                values.len(),
+                self.element
+            ));
+        }
+
+        let len = values.len() / self.element.components;
+        if let Some(expected) = self.len {
+            if expected != len {
+                return Err(format!(
+                    "`{}` is declared with {} elements, but the data embedded has {}",
+                    self.name, expected, len
+                ));
+            }
+        }
+
+        let elements = values
+            .chunks_exact(self.element.components)
+            .map(|components| self.element.literal(components))
+            .collect::<Vec<_>>();
+        Ok(format!(
+            "const {}: array<{}, {}> = array<{}, {}>({});",
+            self.name,
+            self.element,
+            len,
+            self.element,
+            len,
+            elements.join(", ")
+        ))
+    }
+}
+
+/// Finds the names of the arrays declared by every live `#embed` directive in a shader's source, which are the ones that
+/// [`replace_embeds`] replaces. A condition which can't be evaluated is reported when the embeds are replaced, so no
+/// names are found in a source with one.
+pub(crate) fn embedded_names<'a>(
+    source: &'a str,
+    shader_defs: &HashMap<String, ShaderDefValue>,
+) -> Vec<&'a str> {
+    conditionals::live_directives(source, shader_defs)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|directive| match directive {
+            Directive::Other {
+                name: "embed",
+                args,
+                ..
+            } => Embed::parse(args).ok().map(|embed| embed.name),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Replaces each live `#embed` directive with a `const` array of the data in the file that it gives, which is resolved in
+/// the same way as an import. Gives the new source along with every data file read.
+pub(crate) fn replace_embeds(
+    source: &str,
+    importing: &Module,
+    search_paths: &SearchPaths,
+    shader_defs: &HashMap<String, ShaderDefValue>,
+) -> Result<(String, Vec<PathBuf>), Vec<String>> {
+    let mut new_src = source.to_owned();
+    let mut data_files = Vec::new();
+    let mut errors = Vec::new();
+
+    // Replace from the end of the source, so that the spans of earlier directives aren't moved
+    let directives = conditionals::live_directives(source, shader_defs)
+        .map_err(|error| vec![format!("in file `{}`: {}", importing, error)])?;
+    for directive in directives.into_iter().rev() {
+        let Directive::Other {
+            name: "embed",
+            args,
+            span,
+        } = directive
+        else {
+            continue;
+        };
+
+        let embedded = Embed::parse(args).and_then(|embed| {
+            let path = Module::resolve_file(importing, search_paths, embed.path)
+                .map_err(|e| format!("{}", e))?;
+            let values = embed.read_values(&path)?;
+            data_files.push(path);
+            embed.to_const(&values)
+        });
+        match embedded {
+            Ok(embedded) => new_src.replace_range(span, &embedded),
+            Err(e) => errors.push(format!("in `#embed` in file `{}`: {}", importing, e)),
+        }
+    }
+
+    if !errors.is_empty() {
+        errors.reverse();
+        return Err(errors);
+    }
+    data_files.reverse();
+    Ok((new_src, data_files))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::test_shaders;
+
+    #[test]
+    fn finds_embedded_names() {
+        let source =
+            "#embed \"a.csv\" as A: array<u32>\n// #embed \"b.csv\" as B: array<u32>\nconst C = 1;";
+        assert_eq!(embedded_names(source, &HashMap::new()), vec!["A"]);
+
+        let source = "#ifdef LARGE\n#embed \"large.csv\" as TABLE: array<u32>\n#else\n#embed \"small.csv\" as SMALL_TABLE: array<u32>\n#endif\n";
+        assert_eq!(embedded_names(source, &HashMap::new()), vec!["SMALL_TABLE"]);
+        let large = HashMap::from([("LARGE".to_owned(), ShaderDefValue::Bool(true))]);
+        assert_eq!(embedded_names(source, &large), vec!["TABLE"]);
+    }
+
+    #[test]
+    fn writes_every_i32_as_a_valid_literal() {
+        assert_eq!(Scalar::I32.parse_text("-7"), Some("-7i".to_owned()));
+        assert_eq!(
+            Scalar::I32.parse_text("-2147483648"),
+            Some("(-2147483647i - 1i)".to_owned())
+        );
+        assert_eq!(
+            Scalar::I32.parse_bytes(i32::MIN.to_le_bytes()),
+            Some("(-2147483647i - 1i)".to_owned())
+        );
+
+        // The expression is in range, unlike `-2147483648i`
+        let literal = Scalar::I32.parse_text("-2147483648").unwrap();
+        let source = format!("const MIN: i32 = {};", literal);
+        let module = naga::front::wgsl::parse_str(&source).expect("literal is in range");
+        let mut validator = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        );
+        assert!(validator.validate(&module).is_ok());
+    }
+
+    #[test]
+    fn embeds_data_as_const_array() {
+        let module = Module::from_path(test_shaders::shader("embed/table.wgsl"));
+        let source = module.read_to_string(&HashMap::new()).unwrap();
+        let search_paths = SearchPaths::new(None, Vec::new());
+        let (source, data_files) =
+            replace_embeds(&source, &module, &search_paths, &HashMap::new()).unwrap();
+        assert_eq!(
+            source.trim(),
+            "const TABLE: array<u32, 4> = array<u32, 4>(1u, 2u, 3u, 7u);"
+        );
+        assert_eq!(data_files.len(), 1);
+    }
+
+    #[test]
+    fn reports_length_mismatch() {
+        let module = Module::from_path(test_shaders::shader("embed/wrong_length.wgsl"));
+        let source = module.read_to_string(&HashMap::new()).unwrap();
+        let search_paths = SearchPaths::new(None, Vec::new());
+        let errors = replace_embeds(&source, &module, &search_paths, &HashMap::new()).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![format!(
+                "in `#embed` in file `{}`: `TABLE` is declared with 5 elements, but the data embedded has 4",
+                module
+            )]
+        );
+    }
+}
@@ -1,24 +1,35 @@
-use std::borrow::Cow;
+use std::{
+    borrow::Cow, collections::HashMap, error::Error, fmt::Display, ops::Range, path::PathBuf,
+};
 
 use naga_oil::compose::{Composer, ComposerError, ComposerErrorInner};
+use proc_macro2::Span;
 use regex::{Captures, Regex};
 
+use crate::{
+    imports,
+    includes::SplicedSource,
+    lexer::{self, Directive},
+    source_map::{ModuleSource, SourceMap},
+};
+
 lazy_static::lazy_static! {
     static ref UNDECORATE_REGEX: Regex = Regex::new("_naga_oil_mod_([A-Z0-9]*)_member").unwrap();
 }
 
 fn demangle_mod_names(source: &str, pad: bool) -> Cow<'_, str> {
     UNDECORATE_REGEX.replace_all(source, |capture: &Captures<'_>| {
-        let module = capture.get(1).unwrap();
-        let module = String::from_utf8(
-            data_encoding::BASE32_NOPAD
-                .decode(module.as_str().as_bytes())
-                .unwrap(),
-        )
-        .unwrap();
+        let module = data_encoding::BASE32_NOPAD
+            .decode(capture[1].as_bytes())
+            .ok()
+            .and_then(|module| String::from_utf8(module).ok());
+        // Leave anything which only looks like a mangled name as it is
+        let Some(module) = module else {
+            return capture[0].to_owned();
+        };
 
         if pad {
-            let original_len = capture.get(0).unwrap().len();
+            let original_len = capture[0].len();
             format!("{:>len$}::", module, len = original_len - 2)
         } else {
             format!("{}::", module)
@@ -26,41 +37,167 @@ fn demangle_mod_names(source: &str, pad: bool) -> Cow<'_, str> {
     })
 }
 
-pub(crate) fn format_compose_error(e: ComposerError, composer: &Composer) -> String {
-    let (source_name, source, offset) = match &e.source {
+/// A position in a shader file, with the line and column starting from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Location {
+    pub(crate) file: PathBuf,
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.file.display(), self.line, self.column)
+    }
+}
+
+/// An error found while including a shader, along with the place in a shader file that it was found, if known.
+#[derive(Debug, Clone)]
+pub(crate) struct Diagnostic {
+    pub(crate) message: String,
+    pub(crate) location: Option<Location>,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(message: String) -> Self {
+        Self {
+            message,
+            location: None,
+        }
+    }
+}
+
+/// Diagnostics with a location start with it, in the same form as the compiler's own, so that editors can link to it.
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "{}: {}", location, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Gives a `compile_error!` item with the given message, spanned so that it is shown on the given tokens.
+pub(crate) fn compile_error_item(message: &str, span: Span) -> syn::Item {
+    syn::parse_quote_spanned! {span=>
+        compile_error!(#message);
+    }
+}
+
+/// Gives a note for every item renamed with `as` in a source's imports that an error message refers to, by either name,
+/// since `naga_oil` reports items by the name that they are declared with.
+fn alias_notes(message: &str, source: &str) -> String {
+    let mut notes = String::new();
+    for directive in lexer::directives(source) {
+        let Directive::Import { path, tail, .. } = directive else {
+            continue;
+        };
+        for item in imports::import_items(tail) {
+            let Some(alias) = item.alias else {
+                continue;
+            };
+            let full_name = format!("{}::{}", path, item.name);
+            let alias_pattern = Regex::new(&format!(r"\b{}\b", regex::escape(alias)))
+                .expect("escaped pattern is valid");
+            if message.contains(&full_name) || alias_pattern.is_match(message) {
+                notes += &format!("\nnote: `{}` is imported as `{}`", full_name, alias);
+            }
+        }
+    }
+    notes
+}
+
+/// Formats an error from `naga_oil`, locating it within the shader file that it was found in. Errors within a module
+/// which includes other files are located within the included file, and note which line of the module it was spliced
+/// into, given the spliced sources of the modules which include files or have the prelude added, by the name of each
+/// module, or by the path of the root module.
+/// Errors within an imported module end with the note tracing how it was imported, given by the name of each module.
+pub(crate) fn format_compose_error(
+    e: ComposerError,
+    composer: &Composer,
+    includes: &HashMap<String, SplicedSource>,
+    import_notes: &HashMap<String, String>,
+) -> Diagnostic {
+    let (source_name, source, offset, file_path) = match &e.source {
         naga_oil::compose::ErrSource::Module {
             name,
             offset,
             defs: _,
         } => {
-            let source = composer
-                .module_sets
-                .get(name)
-                .unwrap_or_else(|| {
-                    panic!(
-                        "while handling error could not find module {}: {:?}",
-                        name, e
-                    )
-                })
-                .sanitized_source
-                .clone();
-            (name, source, *offset)
+            // Without the module's source, the error can't be shown in context
+            let Some(module_set) = composer.module_sets.get(name) else {
+                let import_note = import_notes.get(name).cloned().unwrap_or_default();
+                return Diagnostic::new(format!("{}", e) + &import_note);
+            };
+            (
+                name,
+                module_set.sanitized_source.clone(),
+                *offset,
+                &module_set.file_path,
+            )
         }
         naga_oil::compose::ErrSource::Constructing {
             source,
             path,
             offset,
-        } => (path, source.clone(), *offset),
+        } => (path, source.clone(), *offset, path),
     };
 
     let source = " ".repeat(offset) + &source;
 
-    match e.inner {
+    let source_location = match &e.inner {
+        ComposerErrorInner::WgslParseError(e) => e.location(&source),
+        ComposerErrorInner::ShaderValidationError(e) => e.location(&source),
+        _ => None,
+    };
+    let line = source_location.map(|location| location.line_number as usize);
+    let spliced = includes.get(source_name.as_str());
+    let location = source_location.and_then(|location| {
+        let line = location.line_number as usize;
+        let mut column = location.line_position as usize;
+        // The source is padded to the offset of the module, which only moves the first line
+        if line == 1 {
+            column = column.saturating_sub(offset).max(1);
+        }
+        // Errors in the prelude's imports aren't in any file
+        match spliced {
+            Some(spliced) => {
+                let (file, file_line) = spliced.origin(line)?;
+                Some(Location {
+                    file: file.to_path_buf(),
+                    line: file_line,
+                    column,
+                })
+            }
+            None => Some(Location {
+                file: PathBuf::from(file_path),
+                line,
+                column,
+            }),
+        }
+    });
+    let include_note = line
+        .zip(spliced.filter(|spliced| spliced.has_includes()))
+        .and_then(|(line, spliced)| {
+            let (file, file_line) = spliced.origin(line)?;
+            Some(format!(
+                "\nnote: line {} of `{}` is line {} of `{}`, spliced in with `#include`",
+                line,
+                source_name,
+                file_line,
+                file.display()
+            ))
+        })
+        .unwrap_or_default();
+
+    let message = match e.inner {
         ComposerErrorInner::WgslParseError(e) => {
             let wgsl_error = e.emit_to_string_with_path(&source, source_name);
 
             // Demangle first line that probably contains type but not in context, so no padding required
-            let (first_line, other_lines) = wgsl_error.split_once('\n').unwrap();
+            let (first_line, other_lines) = wgsl_error
+                .split_once('\n')
+                .unwrap_or((wgsl_error.as_str(), ""));
             let first_line = demangle_mod_names(first_line, false);
 
             // Demangle anything else
@@ -74,5 +211,93 @@ pub(crate) fn format_compose_error(e: ComposerError, composer: &Composer) -> Str
             e.emit_to_string(&source)
         ),
         _ => format!("{}", e),
+    };
+    let notes = alias_notes(&message, &source);
+    let import_note = import_notes
+        .get(source_name.as_str())
+        .map(String::as_str)
+        .unwrap_or_default();
+    Diagnostic {
+        message: message + &include_note + &notes + import_note,
+        location,
+    }
+}
+
+/// Formats an error from validating a composed module, shown in context within the source of the module that the error
+/// is in, or as the chain of errors which caused it if it can't be located.
+pub(crate) fn format_validation_error(
+    e: naga::WithSpan<naga::valid::ValidationError>,
+    module: &naga::Module,
+    source_map: &SourceMap,
+) -> Diagnostic {
+    // Errors can only be shown within one source, so labels in other modules are left out
+    let mut located: Vec<(&ModuleSource, Range<usize>, String)> = Vec::new();
+    for (span, label) in e.spans() {
+        let Some((source, range)) = source_map.locate(module, *span) else {
+            continue;
+        };
+        if located
+            .first()
+            .is_some_and(|(first, ..)| first.name != source.name)
+        {
+            continue;
+        }
+        located.push((source, range, label.clone()));
+    }
+
+    let Some((source, _, _)) = located.first() else {
+        let mut e_base: &dyn Error = e.as_inner();
+        let mut message = format!("{}", e);
+        let mut error_count = 1;
+        while let Some(e) = e_base.source() {
+            message = format!("{}: \n{}{}", message, "    ".repeat(error_count), e);
+            e_base = e;
+            error_count += 1;
+        }
+        return Diagnostic::new(demangle_mod_names(&message, false).into_owned());
+    };
+    let source = *source;
+
+    let mut relocated = naga::WithSpan::new(e.into_inner());
+    for (_, range, label) in &located {
+        relocated = relocated.with_span(
+            naga::Span::new(range.start as u32, range.end as u32),
+            label.clone(),
+        );
+    }
+
+    let location = relocated.location(&source.source).and_then(|location| {
+        let line = location.line_number as usize;
+        let column = location.line_position as usize;
+        match &source.spliced {
+            Some(spliced) => {
+                let (file, file_line) = spliced.origin(line)?;
+                Some(Location {
+                    file: file.to_path_buf(),
+                    line: file_line,
+                    column,
+                })
+            }
+            None => Some(Location {
+                file: source.file.clone(),
+                line,
+                column,
+            }),
+        }
+    });
+
+    let validation_error = relocated.emit_to_string_with_path(&source.source, &source.name);
+    let (first_line, other_lines) = validation_error
+        .split_once('\n')
+        .unwrap_or((validation_error.as_str(), ""));
+    let message = format!(
+        "failed to build a valid final module: {}\n{}",
+        demangle_mod_names(first_line, false),
+        demangle_mod_names(other_lines, true)
+    );
+    let notes = alias_notes(&message, &source.source);
+    Diagnostic {
+        message: message + &notes,
+        location,
     }
 }
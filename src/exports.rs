@@ -1,10 +1,6 @@
 use std::collections::HashSet;
 
-use regex::Regex;
-
-lazy_static::lazy_static! {
-    static ref EXPORT_STRUCT_REGEX: Regex = Regex::new(r"@export\s+struct\s+([^\s]+)").unwrap();
-}
+use crate::lexer::{self, Directive};
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 #[non_exhaustive]
@@ -15,14 +11,16 @@ pub(crate) enum Export {
 /// Removes `@export` statements, replacing them with an equivalent number of spaces so as to not disrupt spans.
 pub(crate) fn strip_exports(source: &str) -> (String, HashSet<Export>) {
     let mut exports = HashSet::new();
+    let mut new_src = source.to_owned();
 
-    let new_src = EXPORT_STRUCT_REGEX.replace_all(source, |group: &regex::Captures<'_>| {
-        let name = group.get(1).unwrap().as_str();
-        exports.insert(Export::Struct {
-            struct_name: name.to_owned(),
-        });
-        group.get(0).unwrap().as_str().replace("@export", "       ")
-    });
+    for directive in lexer::directives(source) {
+        if let Directive::ExportStruct { span, struct_name } = directive {
+            exports.insert(Export::Struct {
+                struct_name: struct_name.to_owned(),
+            });
+            new_src.replace_range(span.clone(), &" ".repeat(span.len()));
+        }
+    }
 
-    (new_src.into_owned(), exports)
+    (new_src, exports)
 }
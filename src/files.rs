@@ -1,4 +1,173 @@
-use std::{ffi::OsStr, ops::Deref, path::PathBuf};
+use std::{
+    cell::OnceCell,
+    collections::HashMap,
+    ffi::OsStr,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use crate::imports;
+
+/// Gives the root folder of the crate being compiled, which is given by cargo.
+pub(crate) fn manifest_dir() -> Result<PathBuf, String> {
+    std::env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .ok_or_else(|| {
+            "`CARGO_MANIFEST_DIR` is not set - the macro should be expanded by cargo".to_owned()
+        })
+}
+
+/// Expands environment variables given as `$NAME` or `${NAME}` in a requested path, such as `$OUT_DIR/generated.wgsl`,
+/// giving the name of the first variable that isn't set if expansion fails.
+pub(crate) fn expand_env_vars(path: &str) -> Result<String, String> {
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        let (name, remaining) = match rest.strip_prefix('{') {
+            Some(braced) => match braced.split_once('}') {
+                Some((name, remaining)) => (name, remaining),
+                None => return Err(braced.to_owned()),
+            },
+            None => {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                rest.split_at(end)
+            }
+        };
+        if name.is_empty() {
+            expanded.push('$');
+            continue;
+        }
+
+        let value = std::env::var(name).map_err(|_| name.to_owned())?;
+        expanded.push_str(&value);
+        rest = remaining;
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+/// Checks if a requested import path is a glob, e.g. `materials/*.wgsl` or `materials/**/*.wgsl`.
+pub(crate) fn is_glob(path: &str) -> bool {
+    path.contains('*')
+}
+
+/// Matches a single file or folder name against a pattern where `*` matches any run of characters.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=name.len())
+                .filter(|i| name.is_char_boundary(*i))
+                .any(|i| wildcard_match(rest, &name[i..]))
+        }
+    }
+}
+
+/// Lists the entries of a folder in sorted order. When the `nightly` feature is enabled on a nightly compiler, the folder
+/// is tracked so that adding or removing files causes the macro to be expanded again. Stable compilers have no way to
+/// track a folder, so only the files found in it are tracked.
+fn sorted_entries(folder: &Path) -> Vec<PathBuf> {
+    #[cfg(nightly_tracking)]
+    proc_macro::tracked_path::path(folder.to_string_lossy());
+
+    let Ok(entries) = std::fs::read_dir(folder) else {
+        return Vec::new();
+    };
+    let mut paths = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+}
+
+fn glob_components(folder: &Path, components: &[&str], files: &mut Vec<PathBuf>) {
+    let Some((component, rest)) = components.split_first() else {
+        return;
+    };
+
+    if *component == "**" {
+        // Match zero folders, then one or more
+        glob_components(folder, rest, files);
+        for path in sorted_entries(folder) {
+            let is_hidden = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            if path.is_dir() && !is_hidden {
+                glob_components(&path, components, files);
+            }
+        }
+    } else if !component.contains('*') {
+        let path = folder.join(component);
+        if rest.is_empty() {
+            if path.is_file() {
+                files.push(path);
+            }
+        } else if path.is_dir() {
+            glob_components(&path, rest, files);
+        }
+    } else {
+        for path in sorted_entries(folder) {
+            let matches = path
+                .file_name()
+                .is_some_and(|name| wildcard_match(component, &name.to_string_lossy()));
+            if !matches {
+                continue;
+            }
+            if rest.is_empty() {
+                if path.is_file() && path.extension() == Some(OsStr::new("wgsl")) {
+                    files.push(path);
+                }
+            } else if path.is_dir() {
+                glob_components(&path, rest, files);
+            }
+        }
+    }
+}
+
+/// Splits an absolute glob pattern into the folder before its first wildcard and the pattern relative to that folder,
+/// giving `None` if the pattern is relative.
+pub(crate) fn split_absolute_glob(pattern: &str) -> Option<(PathBuf, String)> {
+    let path = Path::new(pattern);
+    if !path.is_absolute() {
+        return None;
+    }
+
+    let mut folder = PathBuf::new();
+    let mut rest = Vec::new();
+    for component in path.components() {
+        let name = component.as_os_str().to_string_lossy();
+        if rest.is_empty() && !is_glob(&name) {
+            folder.push(component);
+        } else {
+            rest.push(name.into_owned());
+        }
+    }
+    Some((folder, rest.join("/")))
+}
+
+/// Finds every `.wgsl` file in a folder that matches a glob pattern, where `*` matches any part of a file or folder name
+/// and `**` matches any number of nested folders. Files are given in sorted order.
+pub(crate) fn glob_files(folder: &Path, pattern: &str) -> Vec<PathBuf> {
+    let components = pattern
+        .split('/')
+        .filter(|component| !component.is_empty())
+        .collect::<Vec<_>>();
+    let mut files = Vec::new();
+    glob_components(folder, &components, &mut files);
+    files.sort();
+    files.dedup();
+    files
+}
 
 /// A PathBuf that is absolute, exists and points to a folder that is the root of a Rust module/test/example/executable.
 pub(crate) struct AbsoluteRustRootPathBuf {
@@ -6,16 +175,19 @@ pub(crate) struct AbsoluteRustRootPathBuf {
 }
 
 impl AbsoluteRustRootPathBuf {
-    /// Creates a new [`AbsoluteRustRootPathBuf`], panicking if any requirements aren't met.
-    pub(crate) fn new(path: PathBuf) -> Self {
-        assert!(
-            path.is_dir(),
-            "`{}` is not a directory - expected a Rust root directory",
-            path.display()
-        );
-        assert!(path.is_absolute(), "`{}` is not absolute", path.display());
+    /// Creates a new [`AbsoluteRustRootPathBuf`], erroring if any requirements aren't met.
+    pub(crate) fn new(path: PathBuf) -> Result<Self, String> {
+        if !path.is_dir() {
+            return Err(format!(
+                "`{}` is not a directory - expected a Rust root directory",
+                path.display()
+            ));
+        }
+        if !path.is_absolute() {
+            return Err(format!("`{}` is not absolute", path.display()));
+        }
 
-        Self { inner: path }
+        Ok(Self { inner: path })
     }
 }
 
@@ -33,28 +205,132 @@ impl std::fmt::Debug for AbsoluteRustRootPathBuf {
     }
 }
 
+/// The folders that imports are resolved against, other than the folder containing the importing file.
+pub(crate) struct SearchPaths {
+    source_root: Option<AbsoluteRustRootPathBuf>,
+    include_dirs: Vec<PathBuf>,
+    /// Every WGSL file in the folders, in search order. Only found when first needed.
+    files: OnceCell<Vec<PathBuf>>,
+    /// The files in the folders which declare a logical name with `#define_import_path`, by that name.
+    /// Only built when a logical name is first imported.
+    logical_modules: OnceCell<HashMap<String, PathBuf>>,
+}
+
+impl SearchPaths {
+    pub(crate) fn new(
+        source_root: Option<AbsoluteRustRootPathBuf>,
+        include_dirs: Vec<PathBuf>,
+    ) -> Self {
+        Self {
+            source_root,
+            include_dirs,
+            files: OnceCell::new(),
+            logical_modules: OnceCell::new(),
+        }
+    }
+
+    /// Gives the folders to search, in the order that they should be searched.
+    pub(crate) fn folders(&self) -> impl Iterator<Item = &Path> {
+        self.source_root
+            .iter()
+            .map(|source_root| source_root.as_path())
+            .chain(self.include_dirs.iter().map(|dir| dir.as_path()))
+    }
+
+    /// Gives every WGSL file in the folders, in search order, finding them when first needed. A file in more than one
+    /// of the folders is given once for each.
+    pub(crate) fn files(&self) -> &[PathBuf] {
+        self.files.get_or_init(|| {
+            let mut files = Vec::new();
+            for folder in self.folders() {
+                collect_wgsl_files(folder, &mut files);
+            }
+            files
+        })
+    }
+
+    /// Gives the files in the folders which declare a logical name, by that name, finding them when first needed.
+    fn logical_modules(&self) -> &HashMap<String, PathBuf> {
+        self.logical_modules.get_or_init(|| {
+            let mut modules = HashMap::new();
+            for path in self.files() {
+                let Ok(source) = std::fs::read_to_string(path) else {
+                    continue;
+                };
+                if let Some(name) = imports::defined_import_path(&source) {
+                    modules
+                        .entry(name.to_owned())
+                        .or_insert_with(|| path.clone());
+                }
+            }
+            modules
+        })
+    }
+
+    /// Finds the file which declares the given logical name with `#define_import_path`. Where more than one file
+    /// declares the same name, the first found in search order is used.
+    pub(crate) fn logical_module(&self, name: &str) -> Option<&Path> {
+        self.logical_modules().get(name).map(PathBuf::as_path)
+    }
+
+    /// Gives every logical name declared by a file in the folders.
+    pub(crate) fn logical_names(&self) -> impl Iterator<Item = &str> {
+        self.logical_modules().keys().map(String::as_str)
+    }
+}
+
+/// Finds every WGSL file in a folder, in sorted order, skipping hidden folders and build output folders.
+pub(crate) fn collect_wgsl_files(folder: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(folder) else {
+        return;
+    };
+
+    let mut paths = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            let is_hidden = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            let is_build_output = path.join("CACHEDIR.TAG").is_file();
+            if !is_hidden && !is_build_output {
+                collect_wgsl_files(&path, files);
+            }
+        } else if path.extension() == Some(OsStr::new("wgsl")) {
+            files.push(path);
+        }
+    }
+}
+
 /// A PathBuf that is absolute, exists and points to a Rust file
+#[derive(Clone)]
 pub(crate) struct AbsoluteRustFilePathBuf {
     inner: PathBuf,
 }
 
 impl AbsoluteRustFilePathBuf {
-    /// Creates a new [`AbsoluteRustFilePathBuf`], panicking if any requirements aren't met.
-    pub(crate) fn new(path: PathBuf) -> Self {
-        assert!(
-            path.is_file(),
-            "`{}` is not a file - expected a `rs` file",
-            path.display()
-        );
-        assert!(path.is_absolute(), "`{}` is not absolute", path.display());
-        assert_eq!(
-            path.extension(),
-            Some(OsStr::new("rs")),
-            "`{}` does not have a `.rs` extension",
-            path.display()
-        );
+    /// Creates a new [`AbsoluteRustFilePathBuf`], erroring if any requirements aren't met.
+    pub(crate) fn new(path: PathBuf) -> Result<Self, String> {
+        if !path.is_file() {
+            return Err(format!(
+                "`{}` is not a file - expected a `rs` file",
+                path.display()
+            ));
+        }
+        if !path.is_absolute() {
+            return Err(format!("`{}` is not absolute", path.display()));
+        }
+        if path.extension() != Some(OsStr::new("rs")) {
+            return Err(format!(
+                "`{}` does not have a `.rs` extension",
+                path.display()
+            ));
+        }
 
-        Self { inner: path }
+        Ok(Self { inner: path })
     }
 
     /// Given a path to a Rust file, gives a best guess to the source of a module containing that file. Uses the following logic:
@@ -73,7 +349,7 @@ impl AbsoluteRustFilePathBuf {
         let mut source_root = self.parent()?;
 
         let res = |source_root: &std::path::Path| {
-            Some(AbsoluteRustRootPathBuf::new(source_root.to_path_buf()))
+            AbsoluteRustRootPathBuf::new(source_root.to_path_buf()).ok()
         };
 
         // If the parent folder of the file is a sibling of a `Cargo.toml` file
@@ -150,22 +426,25 @@ pub(crate) struct AbsoluteWGSLFilePathBuf {
 }
 
 impl AbsoluteWGSLFilePathBuf {
-    /// Creates a new [`AbsoluteWGSLFilePathBuf`], panicking if any requirements aren't met.
-    pub(crate) fn new(path: PathBuf) -> Self {
-        assert!(
-            path.is_file(),
-            "`{}` is not a file - expected a `wgsl` file",
-            path.display()
-        );
-        assert!(path.is_absolute(), "`{}` is not absolute", path.display());
-        assert_eq!(
-            path.extension(),
-            Some(OsStr::new("wgsl")),
-            "`{}` does not have a `.wgsl` extension",
-            path.display()
-        );
+    /// Creates a new [`AbsoluteWGSLFilePathBuf`], erroring if any requirements aren't met.
+    pub(crate) fn new(path: PathBuf) -> Result<Self, String> {
+        if !path.is_file() {
+            return Err(format!(
+                "`{}` is not a file - expected a `wgsl` file",
+                path.display()
+            ));
+        }
+        if !path.is_absolute() {
+            return Err(format!("`{}` is not absolute", path.display()));
+        }
+        if path.extension() != Some(OsStr::new("wgsl")) {
+            return Err(format!(
+                "`{}` does not have the required `.wgsl` extension",
+                path.display()
+            ));
+        }
 
-        Self { inner: path }
+        Ok(Self { inner: path })
     }
 }
 
@@ -182,3 +461,93 @@ impl std::fmt::Debug for AbsoluteWGSLFilePathBuf {
         self.inner.fmt(f)
     }
 }
+
+/// Helpers for tests which read the shaders in `tests/shaders`.
+#[cfg(test)]
+pub(crate) mod test_shaders {
+    use std::path::PathBuf;
+
+    use super::AbsoluteWGSLFilePathBuf;
+
+    /// The folder containing the shaders read by tests.
+    pub(crate) fn folder() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("shaders")
+    }
+
+    /// Gives the path of a shader read by tests, relative to `tests/shaders`.
+    pub(crate) fn shader(path: &str) -> AbsoluteWGSLFilePathBuf {
+        AbsoluteWGSLFilePathBuf::new(folder().join(path)).expect("test shaders exist")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_env_vars() {
+        let name = env!("CARGO_PKG_NAME");
+        assert_eq!(
+            expand_env_vars("$CARGO_PKG_NAME/shader.wgsl"),
+            Ok(format!("{}/shader.wgsl", name))
+        );
+        assert_eq!(
+            expand_env_vars("${CARGO_PKG_NAME}_shader.wgsl"),
+            Ok(format!("{}_shader.wgsl", name))
+        );
+        assert_eq!(expand_env_vars("a$/b.wgsl"), Ok("a$/b.wgsl".to_owned()));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildcard_match("*.wgsl", "metal.wgsl"));
+        assert!(wildcard_match("m*l.wgsl", "metal.wgsl"));
+        assert!(!wildcard_match("m*l.wgsl", "wood.wgsl"));
+        assert!(!wildcard_match("*.wgsl", "notes.txt"));
+    }
+
+    #[test]
+    fn finds_files_matching_globs() {
+        let folder = test_shaders::folder().join("globs");
+        let materials = folder.join("materials");
+        assert_eq!(
+            glob_files(&folder, "materials/*.wgsl"),
+            [materials.join("metal.wgsl"), materials.join("wood.wgsl")]
+        );
+
+        // Hidden folders are skipped
+        assert_eq!(
+            glob_files(&folder, "materials/**/*.wgsl"),
+            [
+                materials.join("metal.wgsl"),
+                materials.join("nested").join("glass.wgsl"),
+                materials.join("wood.wgsl"),
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_absolute_globs() {
+        let folder = test_shaders::folder().join("globs");
+        let pattern = format!("{}/materials/**/*.wgsl", folder.display());
+        assert_eq!(
+            split_absolute_glob(&pattern),
+            Some((folder.join("materials"), "**/*.wgsl".to_owned()))
+        );
+        assert_eq!(split_absolute_glob("materials/*.wgsl"), None);
+    }
+
+    #[test]
+    fn names_unset_env_vars() {
+        assert_eq!(
+            expand_env_vars("$INCLUDE_WGSL_OIL_UNSET/shader.wgsl"),
+            Err("INCLUDE_WGSL_OIL_UNSET".to_owned())
+        );
+        assert_eq!(
+            expand_env_vars("${OUT_DIR/shader.wgsl"),
+            Err("OUT_DIR/shader.wgsl".to_owned())
+        );
+    }
+}
@@ -5,19 +5,29 @@ use std::{
 };
 
 use daggy::{petgraph::visit::IntoNodeReferences, Walker};
-use regex::{Captures, Regex};
+use regex::Regex;
+
+use naga_oil::compose::ShaderDefValue;
 
 use crate::{
-    files::{AbsoluteRustRootPathBuf, AbsoluteWGSLFilePathBuf},
+    args,
+    conditionals::{self, ConditionError, Define},
+    embed,
+    files::{self, AbsoluteWGSLFilePathBuf, SearchPaths},
+    lexer::{self, Directive},
     module::Module,
+    prelude::Prelude,
+    suggestions,
 };
 
-lazy_static::lazy_static! {
-    static ref IMPORT_CUSTOM_PATH_REGEX: Regex = Regex::new(r"(?:^|\n)\s*#\s*import\s+([^\s]+?\.wgsl)").unwrap();
-    static ref IMPORT_CUSTOM_PATH_AS_REGEX: Regex = Regex::new(r"(?:^|\n)\s*#\s*import\s+([^\s]+?\.wgsl)\s+as\s+([^\s]+)").unwrap();
-    static ref IMPORT_ITEMS_REGEX: Regex = Regex::new(r"(?:^|\n)\s*#\s*import\s+([^\s]+?\.wgsl)\s+([^\s]+(?:\s*,\s*[^\s]+)*)").unwrap();
-    static ref IMPORT_SINGLE_ITEM_REGEX: Regex = Regex::new(r"(?:^|\n)\s*#\s*import\s+([^\s]+?\.wgsl)\s*::\s*([^\s{]+)").unwrap();
-    static ref IMPORT_ITEMS_BRACKETS_REGEX: Regex = Regex::new(r"(?:^|\n)\s*#\s*import\s+([^\s]+?\.wgsl)\s*::\s*\{\s*([^\s]+(?:\s*,\s*[^\s]+)*)\s*\}").unwrap();
+/// Finds the logical name that a file declares with `#define_import_path`, if any.
+pub(crate) fn defined_import_path(source: &str) -> Option<&str> {
+    lexer::directives(source)
+        .into_iter()
+        .find_map(|directive| match directive {
+            Directive::DefineImportPath { name, .. } => Some(name),
+            _ => None,
+        })
 }
 
 /// Finds an arbitrary path between two nodes in a dag.
@@ -31,59 +41,608 @@ fn find_any_path<N, E>(
         .expect("`find_any_path` should only be called when such a path exists")
 }
 
-/// Finds all import declarations in a source file, returning all of the paths given.
-fn all_imports_in_source(source: &str) -> HashSet<&str> {
-    let mut requirements = HashSet::new();
-    for import in IMPORT_CUSTOM_PATH_REGEX.captures_iter(source) {
-        requirements.insert(import.get(1).unwrap().as_str());
+/// Finds all import declarations in a source file which are live with the given shader defs, returning all of the
+/// paths given, along with any parameters given with `with (...)` and the line, starting from 1, that each path is
+/// first imported on.
+fn all_imports_in_source<'a>(
+    source: &'a str,
+    shader_defs: &HashMap<String, ShaderDefValue>,
+) -> Result<Vec<(&'a str, Option<&'a str>, usize)>, ConditionError> {
+    let mut seen = HashSet::new();
+    Ok(conditionals::live_directives(source, shader_defs)?
+        .into_iter()
+        .filter_map(|directive| match directive {
+            Directive::Import {
+                span, path, params, ..
+            } => seen
+                .insert((path, params))
+                .then(|| (path, params, source[..span.start].matches('\n').count() + 1)),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Finds all re-export declarations in a source file which are live with the given shader defs, returning the path,
+/// parameters and everything following the path of each.
+fn all_exports_in_source<'a>(
+    source: &'a str,
+    shader_defs: &HashMap<String, ShaderDefValue>,
+) -> Result<Vec<(&'a str, Option<&'a str>, &'a str)>, ConditionError> {
+    Ok(conditionals::live_directives(source, shader_defs)?
+        .into_iter()
+        .filter_map(|directive| match directive {
+            Directive::Import {
+                path,
+                params,
+                tail,
+                reexport: true,
+                ..
+            } => Some((path, params, tail)),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Finds every file that the prelude's files import, directly or indirectly. Importing the prelude from any of these
+/// would be an import cycle, so they are left without it. Each file is searched with the shader defs given to the
+/// shader, along with the `#define`s and parameters that it would be imported with from the prelude.
+fn prelude_dependencies(
+    prelude: &Prelude,
+    search_paths: &SearchPaths,
+    shader_defs: &HashMap<String, ShaderDefValue>,
+) -> Result<HashSet<Module>, ImportResolutionError> {
+    let mut found = HashSet::new();
+    let mut search_front = prelude
+        .modules()
+        .map(|module| (module.clone(), shader_defs.clone()))
+        .collect::<std::collections::VecDeque<_>>();
+    while let Some((module, shader_defs)) = search_front.pop_front() {
+        let spliced = module
+            .read_spliced(&shader_defs)
+            .map_err(|message| ImportResolutionError::Include { message })?;
+        let source = spliced.source.as_str();
+        let condition_error = |error: ConditionError| {
+            let (file, line) = spliced
+                .origin(error.line)
+                .map_or((module.path(), None), |(file, line)| {
+                    (file.clone(), Some(line))
+                });
+            ImportResolutionError::Condition {
+                site: ImportSite { file, line },
+                message: error.message,
+            }
+        };
+
+        let mut import_defs = shader_defs.clone();
+        import_defs.extend(
+            conditionals::live_defines(source, &shader_defs)
+                .map_err(condition_error)?
+                .into_iter()
+                .map(|define| (define.name, define.value)),
+        );
+        let imports = all_imports_in_source(source, &shader_defs).map_err(condition_error)?;
+        for (requested, params, _) in imports {
+            for import in resolve_instances(&module, search_paths, requested, params)? {
+                if found.insert(import.clone()) {
+                    let mut import_defs = import_defs.clone();
+                    import_defs.extend(import.params().iter().cloned());
+                    search_front.push_back((import, import_defs));
+                }
+            }
+        }
     }
-    for import in IMPORT_CUSTOM_PATH_AS_REGEX.captures_iter(source) {
-        requirements.insert(import.get(1).unwrap().as_str());
+    Ok(found)
+}
+
+/// Items that a file re-exports with `#export`, e.g. `#export utils.wgsl::{MyStruct, my_fn}`.
+#[derive(Clone)]
+pub(crate) struct ReExport {
+    /// The modules that the items are exported from, of which there may be several for a glob.
+    modules: Vec<Module>,
+    /// The name that each item is declared with and the name that it is exported as, if renamed with `as`, or `None` if
+    /// every item of the modules is exported.
+    items: Option<Vec<(String, Option<String>)>>,
+}
+
+/// The items that each module re-exports.
+pub(crate) type ReExports = HashMap<Module, Vec<ReExport>>;
+
+/// Finds the module that declares an item imported from the given module, following the module's re-exports, and the
+/// re-exports of the modules that it exports from. Gives the declaring module and the name that the item is declared with.
+///
+/// Re-exports are also imports, so `ImportOrder` has already ruled out any cycles between them. The shader defs decide
+/// which files are included by each module.
+fn resolve_item(
+    module: &Module,
+    name: &str,
+    re_exports: &ReExports,
+    shader_defs: &HashMap<String, ShaderDefValue>,
+) -> Option<(Module, String)> {
+    // Every module has been read when calculating the import order, so any problem reading it has been reported
+    if module
+        .read_to_string(shader_defs)
+        .is_ok_and(|source| declares_item(&source, name, shader_defs))
+    {
+        return Some((module.clone(), name.to_owned()));
+    }
+
+    for re_export in re_exports.get(module).into_iter().flatten() {
+        let exported_name = match &re_export.items {
+            None => Some(name.to_owned()),
+            Some(items) => items
+                .iter()
+                .find(|(item, alias)| alias.as_deref().unwrap_or(item) == name)
+                .map(|(item, _)| item.clone()),
+        };
+        let Some(exported_name) = exported_name else {
+            continue;
+        };
+        for exporting in &re_export.modules {
+            if let Some(found) = resolve_item(exporting, &exported_name, re_exports, shader_defs) {
+                return Some(found);
+            }
+        }
+    }
+
+    None
+}
+
+/// Gives the name of every item which can be imported from the given module, i.e. every item that it declares and
+/// every item that it re-exports.
+fn importable_names(
+    module: &Module,
+    re_exports: &ReExports,
+    shader_defs: &HashMap<String, ShaderDefValue>,
+) -> Vec<String> {
+    let mut names = module
+        .read_to_string(shader_defs)
+        .map(|source| declared_items(&source, shader_defs))
+        .unwrap_or_default();
+    for re_export in re_exports.get(module).into_iter().flatten() {
+        match &re_export.items {
+            Some(items) => names.extend(
+                items
+                    .iter()
+                    .map(|(item, alias)| alias.clone().unwrap_or_else(|| item.clone())),
+            ),
+            None => {
+                for exporting in &re_export.modules {
+                    names.extend(importable_names(exporting, re_exports, shader_defs));
+                }
+            }
+        }
+    }
+    names
+}
+
+/// Writes the import of a single item for `naga_oil`, renaming the item if it is referred to by a different name than
+/// it is declared with.
+fn item_import(module_name: &str, declared_name: &str, item: &ImportItem<'_>) -> String {
+    if declared_name == item.local_name() {
+        format!("{}::{}", module_name, declared_name)
+    } else {
+        format!(
+            "{}::{} as {}",
+            module_name,
+            declared_name,
+            item.local_name()
+        )
+    }
+}
+
+/// Checks whether an import refers to a file by its logical name, declared with `#define_import_path`.
+fn is_logical_import(request_string: &str) -> bool {
+    !files::is_glob(request_string) && !request_string.ends_with(".wgsl")
+}
+
+/// Parses the parameters given to an import with `with (...)`. Only imports of single `.wgsl` files can be given
+/// parameters, since each set of parameters needs its own name for the file.
+fn parse_import_params(
+    request_string: &str,
+    params: Option<&str>,
+    importer: &Module,
+) -> Result<Vec<(String, ShaderDefValue)>, ImportResolutionError> {
+    let Some(params) = params else {
+        return Ok(Vec::new());
+    };
+    let invalid = |message| ImportResolutionError::Invalid {
+        requested: request_string.to_owned(),
+        importer: importer.clone(),
+        message,
+    };
+
+    if files::is_glob(request_string) || is_logical_import(request_string) {
+        return Err(invalid(
+            "only imports of a single `.wgsl` file can be given parameters with `with (...)`"
+                .to_owned(),
+        ));
+    }
+    args::parse_import_params(params).map_err(invalid)
+}
+
+/// Resolves the modules requested by an import in the given module. Files imported by path are instantiated with the
+/// parameters of the importing module and those given to the import, whereas files imported by their logical name are
+/// shared by every module importing them.
+pub(crate) fn resolve_instances(
+    importing: &Module,
+    search_paths: &SearchPaths,
+    request_string: &str,
+    params: Option<&str>,
+) -> Result<Vec<Module>, ImportResolutionError> {
+    let params = parse_import_params(request_string, params, importing)?;
+    let modules = Module::resolve_modules(importing, search_paths, request_string)?;
+    if is_logical_import(request_string) {
+        return Ok(modules);
+    }
+    Ok(modules
+        .into_iter()
+        .map(|module| module.instantiate(importing, &params))
+        .collect())
+}
+
+/// Checks if a file's source, with its comments stripped, mentions a shader def, e.g. in a condition or as `#NAME`.
+fn refers_to(source: &str, def: &str) -> bool {
+    let pattern = format!(r"\b{}\b", regex::escape(def));
+    Regex::new(&pattern)
+        .expect("escaped pattern is valid")
+        .is_match(source)
+}
+
+/// Checks if a file declares a top-level item with the given name, with the given shader defs.
+fn declares_item(source: &str, name: &str, shader_defs: &HashMap<String, ShaderDefValue>) -> bool {
+    declared_items(source, shader_defs)
+        .iter()
+        .any(|item| item == name)
+}
+
+lazy_static::lazy_static! {
+    static ref DECLARATION_REGEX: Regex = Regex::new(
+        r"\b(?:fn|struct|const|var(?:\s*<[^>]*>)?|alias|override)\s+([A-Za-z_][A-Za-z0-9_]*)",
+    )
+    .expect("pattern is valid");
+}
+
+/// Finds the names of every top-level item declared in a file, including the arrays declared by the `#embed`s which are
+/// live with the given shader defs. Anything commented out is ignored.
+fn declared_items(source: &str, shader_defs: &HashMap<String, ShaderDefValue>) -> Vec<String> {
+    let code = lexer::strip_comments(source);
+
+    // Declarations within braces are fields or locals rather than items
+    let mut depth = 0usize;
+    let mut counted = 0;
+    let mut names = Vec::new();
+    for captures in DECLARATION_REGEX.captures_iter(&code) {
+        let start = captures.get(0).expect("whole match is present").start();
+        for c in code[counted..start].chars() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        counted = start;
+        if depth == 0 {
+            names.push(captures[1].to_owned());
+        }
+    }
+    names.extend(
+        embed::embedded_names(source, shader_defs)
+            .into_iter()
+            .map(str::to_owned),
+    );
+    names
+}
+
+/// A single item requested by an import, e.g. `my_fn as util_fn` in `#import utils.wgsl::{my_fn as util_fn}`.
+pub(crate) struct ImportItem<'a> {
+    pub(crate) name: &'a str,
+    /// The name that the item is given in the importing file, if it is renamed with `as`.
+    pub(crate) alias: Option<&'a str>,
+}
+
+impl<'a> ImportItem<'a> {
+    /// The name that the item is referred to by in the importing file.
+    pub(crate) fn local_name(&self) -> &'a str {
+        self.alias.unwrap_or(self.name)
     }
-    for import in IMPORT_ITEMS_REGEX.captures_iter(source) {
-        requirements.insert(import.get(1).unwrap().as_str());
+
+    /// Whether the item is a single identifier, rather than a nested path such as `lighting::{light}`.
+    fn is_ident(&self) -> bool {
+        self.name.chars().all(|c| c.is_alphanumeric() || c == '_')
     }
-    for import in IMPORT_SINGLE_ITEM_REGEX.captures_iter(source) {
-        requirements.insert(import.get(1).unwrap().as_str());
+}
+
+impl Display for ImportItem<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.alias {
+            Some(alias) => write!(f, "{} as {}", self.name, alias),
+            None => write!(f, "{}", self.name),
+        }
     }
-    for import in IMPORT_ITEMS_BRACKETS_REGEX.captures_iter(source) {
-        requirements.insert(import.get(1).unwrap().as_str());
+}
+
+/// Parses a comma separated list of items, with or without surrounding braces, e.g. `{my_fn as util_fn, MyStruct}`.
+fn parse_item_list(items: &str) -> Vec<ImportItem<'_>> {
+    let items = items.trim();
+    let items = items
+        .strip_prefix('{')
+        .and_then(|items| items.strip_suffix('}'))
+        .unwrap_or(items);
+
+    items
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let mut words = item.split_whitespace();
+            let name = words.next().expect("item is not empty");
+            let alias = match (words.next(), words.next()) {
+                (Some("as"), Some(alias)) => Some(alias),
+                _ => None,
+            };
+            ImportItem { name, alias }
+        })
+        .collect()
+}
+
+/// Finds the items requested by an import from everything following its path, e.g. `::{my_fn as util_fn, MyStruct}`
+/// or `::my_fn as util_fn`. Imports of a whole module, e.g. `as Utils`, request no items.
+pub(crate) fn import_items(tail: &str) -> Vec<ImportItem<'_>> {
+    match tail.strip_prefix("::") {
+        Some(items) => parse_item_list(items),
+        None => Vec::new(),
     }
-    requirements
 }
 
-/// Finds all import declarations in a source file, returning all of the paths given.
-fn replace_import_names_in_source(source: &str, subs: impl Fn(&str) -> Option<String>) -> String {
-    let source = IMPORT_CUSTOM_PATH_REGEX.replace_all(source, |capture: &Captures<'_>| {
-        let full = capture.get(0).unwrap().as_str();
+/// Checks that no two imports in a file bring items into scope with the same name, giving an error for each clash.
+fn check_item_names(directives: &[Directive<'_>], importing: &Module) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut seen = HashMap::<&str, (&str, ImportItem<'_>)>::new();
 
-        let name = capture.get(1).unwrap().as_str();
-        let sub = match subs(name) {
-            Some(sub) => sub,
-            None => return full.to_owned(),
+    for directive in directives {
+        let Directive::Import { path, tail, .. } = directive else {
+            continue;
         };
+        for item in import_items(tail) {
+            match seen.get(item.local_name()) {
+                Some((other_path, other_item))
+                    if (*other_path, other_item.name) != (*path, item.name) =>
+                {
+                    let prefix = path
+                        .trim_end_matches(".wgsl")
+                        .rsplit(['/', ':'])
+                        .next()
+                        .unwrap_or(*path);
+                    errors.push(format!(
+                        "`{}` is imported into `{}` both as `{}::{}` and as `{}::{}` - \
+                        rename one of them with `as`, e.g. `#import {}::{{{} as {}_{}}}`",
+                        item.local_name(),
+                        importing,
+                        other_path,
+                        other_item,
+                        path,
+                        item,
+                        path,
+                        item.name,
+                        prefix,
+                        item.name
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    seen.insert(item.local_name(), (*path, item));
+                }
+            }
+        }
+    }
 
-        // Right alignment is needed for naga_oil to correctly parse rust-style imports:
-        // `#import foo.wgsl::bar` will become `#import      foo::bar`
-        // naga_oil does not support spaces between import items
-        let sub = format!("{:>len$}", sub, len = name.len());
+    errors
+}
+
+/// Rewrites a glob import, e.g. `#import materials/*.wgsl::{wood, stone}`, as an import of every matched module, or of
+/// each item requested from the module that declares it, giving the text to replace everything following `#import` with.
+fn replace_glob_import(
+    request_string: &str,
+    tail: &str,
+    importing: &Module,
+    search_paths: &SearchPaths,
+    module_names: &HashMap<Module, String>,
+    re_exports: &ReExports,
+    shader_defs: &HashMap<String, ShaderDefValue>,
+) -> Result<String, String> {
+    let modules = resolve_instances(importing, search_paths, request_string, None)
+        .map_err(|err| format!("{}", err))?;
+    let module_name = |module: &Module| {
+        module_names.get(module).cloned().ok_or_else(|| {
+            format!(
+                "glob import `{}` matched `{}`, which was not found when calculating the import order",
+                request_string, module
+            )
+        })
+    };
 
-        capture.get(0).unwrap().as_str().replace(name, &sub)
-    });
+    if tail.is_empty() {
+        let names = modules
+            .iter()
+            .map(module_name)
+            .collect::<Result<Vec<_>, _>>()?;
+        return Ok(names.join(", "));
+    }
+    if tail.starts_with("as ") {
+        return Err(format!(
+            "glob import `{}` in file `{}` cannot be renamed with `as` - \
+            import the modules or items it matches instead",
+            request_string, importing
+        ));
+    }
 
-    source.to_string()
+    let items = match tail.strip_prefix("::") {
+        Some(items) => parse_item_list(items),
+        None => parse_item_list(tail),
+    };
+
+    let mut imports = Vec::new();
+    for item in items {
+        let name = item.name;
+        let declaring = modules
+            .iter()
+            .filter_map(|module| {
+                Some((module, resolve_item(module, name, re_exports, shader_defs)?))
+            })
+            .collect::<Vec<_>>();
+        match declaring.as_slice() {
+            [(_, (module, declared_name))] => {
+                imports.push(item_import(&module_name(module)?, declared_name, &item))
+            }
+            [] => {
+                return Err(format!(
+                    "no file matched by glob import `{}` in file `{}` declares `{}`",
+                    request_string, importing, name
+                ))
+            }
+            _ => {
+                return Err(format!(
+                    "more than one file matched by glob import `{}` in file `{}` declares `{}`: {}",
+                    request_string,
+                    importing,
+                    name,
+                    declaring
+                        .iter()
+                        .map(|(module, _)| format!("`{}`", module))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            }
+        }
+    }
+
+    Ok(imports.join(", "))
 }
 
+/// Replaces the paths of imported files with the names that the files are given when composed with `naga_oil`. Items
+/// re-exported by an imported file are imported from the file declaring them instead, and `#export`s become `#import`s.
 pub(crate) fn replace_imports_in_source(
     source: &str,
     importing: &Module,
-    source_root: Option<&AbsoluteRustRootPathBuf>,
+    search_paths: &SearchPaths,
     module_names: &HashMap<Module, String>,
-) -> String {
-    replace_import_names_in_source(source, |request_string| {
-        let import = Module::resolve_module(importing, source_root, request_string).ok()?;
-        module_names.get(&import).cloned()
-    })
+    re_exports: &ReExports,
+    shader_defs: &HashMap<String, ShaderDefValue>,
+) -> Result<String, Vec<String>> {
+    let mut new_src = source.to_owned();
+    let mut errors = Vec::new();
+
+    let directives = conditionals::live_directives(source, shader_defs)
+        .map_err(|error| vec![format!("in file `{}`: {}", importing, error)])?;
+    errors.extend(check_item_names(&directives, importing));
+
+    // Replace from the end of the source, so that the spans of earlier imports aren't moved
+    for directive in directives.into_iter().rev() {
+        let Directive::Import {
+            span,
+            path,
+            path_span,
+            tail,
+            params,
+            params_span,
+            reexport,
+        } = directive
+        else {
+            continue;
+        };
+
+        // To `naga_oil`, a re-export is just an import
+        if reexport {
+            let keyword = span.start
+                + source[span.start..path_span.start]
+                    .find("export")
+                    .expect("re-exports start with `#export`");
+            new_src.replace_range(keyword..keyword + "export".len(), "import");
+        }
+
+        // The parameters are given to `naga_oil` as shader defs instead, so blank them out
+        if let Some(params_span) = params_span {
+            let blank = " ".repeat(params_span.len());
+            new_src.replace_range(params_span, &blank);
+        }
+
+        if files::is_glob(path) {
+            match replace_glob_import(
+                path,
+                tail,
+                importing,
+                search_paths,
+                module_names,
+                re_exports,
+                shader_defs,
+            ) {
+                Ok(sub) => new_src.replace_range(path_span.start..span.end, &sub),
+                Err(e) => errors.push(e),
+            }
+            continue;
+        }
+        let Some(import) = resolve_instances(importing, search_paths, path, params)
+            .ok()
+            .and_then(|imports| imports.into_iter().next())
+        else {
+            continue;
+        };
+        let Some(sub) = module_names.get(&import) else {
+            continue;
+        };
+
+        // `naga_oil` only reports missing items once the whole shader is composed, by their mangled names
+        let mut item_imports = Vec::new();
+        let mut re_exported = false;
+        for item in import_items(tail) {
+            if !item.is_ident() {
+                item_imports.push(format!("{}::{}", sub, item));
+                continue;
+            }
+            let Some((declaring, declared_name)) =
+                resolve_item(&import, item.name, re_exports, shader_defs)
+            else {
+                let suggestions = suggestions::closest(
+                    item.name,
+                    importable_names(&import, re_exports, shader_defs),
+                );
+                errors.push(format!(
+                    "`{}` does not declare or re-export `{}`, which is imported by `{}` with `#import {}::{}`{}",
+                    import,
+                    item.name,
+                    importing,
+                    path,
+                    item,
+                    suggestions::help(&suggestions)
+                ));
+                continue;
+            };
+            re_exported |= declaring != import;
+            let declaring_name = module_names
+                .get(&declaring)
+                .expect("re-exported modules are found when calculating the import order");
+            item_imports.push(item_import(declaring_name, &declared_name, &item));
+        }
+        if re_exported {
+            new_src.replace_range(path_span.start..span.end, &item_imports.join(", "));
+            continue;
+        }
+        if !path.ends_with(".wgsl") {
+            continue;
+        }
+
+        // Right alignment is needed for naga_oil to correctly parse rust-style imports:
+        // `#import foo.wgsl::bar` will become `#import      foo::bar`
+        // naga_oil does not support spaces between import items. Quoted paths lose their quotes
+        let sub = format!("{:>len$}", sub, len = path_span.len());
+        new_src.replace_range(path_span, &sub);
+    }
+
+    if !errors.is_empty() {
+        errors.reverse();
+        return Err(errors);
+    }
+    Ok(new_src)
 }
 
 pub(crate) enum ImportResolutionError {
@@ -93,7 +652,31 @@ pub(crate) enum ImportResolutionError {
     Unresolved {
         requested: String,
         importer: Module,
-        searched: HashSet<PathBuf>,
+        searched: Vec<PathBuf>,
+        /// The paths or logical names closest to the one requested.
+        suggestions: Vec<String>,
+    },
+    Package {
+        requested: String,
+        importer: Module,
+        message: String,
+    },
+    UnsetVariable {
+        requested: String,
+        importer: Module,
+        variable: String,
+    },
+    Invalid {
+        requested: String,
+        importer: Module,
+        message: String,
+    },
+    Include {
+        message: String,
+    },
+    Condition {
+        site: ImportSite,
+        message: String,
     },
 }
 
@@ -111,43 +694,131 @@ impl Display for ImportResolutionError {
                 requested,
                 importer,
                 searched,
+                suggestions,
+            } if searched.is_empty() => {
+                write!(
+                    f,
+                    "could not resolve import `{}` in file `{}`:\nno file in the source folder or include \
+                    directories declares it with `#define_import_path`{}",
+                    requested,
+                    importer,
+                    suggestions::help(suggestions)
+                )
+            }
+            ImportResolutionError::Unresolved {
+                requested,
+                importer,
+                searched,
+                suggestions,
             } => {
                 write!(
                     f,
-                    "could not resolve import `{}` in file `{}`:\nlooked in location(s) {}",
+                    "could not resolve import `{}` in file `{}`:\nlooked in location(s) {}{}",
                     requested,
                     importer,
                     searched
                         .iter()
                         .map(|path| format!("`{}`", path.display()))
-                        .fold(String::new(), |a, b| a + ", " + &b)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    suggestions::help(suggestions)
                 )
             }
+            ImportResolutionError::Package {
+                requested,
+                importer,
+                message,
+            }
+            | ImportResolutionError::Invalid {
+                requested,
+                importer,
+                message,
+            } => {
+                write!(
+                    f,
+                    "could not resolve import `{}` in file `{}`: {}",
+                    requested, importer, message
+                )
+            }
+            ImportResolutionError::UnsetVariable {
+                requested,
+                importer,
+                variable,
+            } => {
+                write!(
+                    f,
+                    "could not resolve import `{}` in file `{}`: environment variable `{}` is not set",
+                    requested, importer, variable
+                )
+            }
+            ImportResolutionError::Include { message } => write!(f, "{}", message),
+            ImportResolutionError::Condition { site, message } => {
+                write!(f, "in {}: {}", site, message)
+            }
+        }
+    }
+}
+
+/// Where a file imports another, or where any other directive in a file is written.
+#[derive(Debug, Clone)]
+pub(crate) struct ImportSite {
+    /// The file that the directive is written in, which is an included file if it was spliced in with `#include`.
+    file: AbsoluteWGSLFilePathBuf,
+    /// The line of the directive, starting from 1, or `None` if the directive was added by the prelude.
+    line: Option<usize>,
+}
+
+impl Display for ImportSite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "`{}:{}`", self.file.display(), line),
+            None => write!(f, "the prelude of `{}`", self.file.display()),
         }
     }
 }
 
 /// Gives all of the files required for a module and the order in which they need to be processed by `naga_oil::compose`.
 pub(crate) struct ImportOrder {
-    dag: daggy::Dag<Module, ()>,
+    /// The files imported, with an edge from each file to each file that it imports.
+    dag: daggy::Dag<Module, ImportSite>,
     node_of_interest: daggy::NodeIndex,
+    /// The live `#define`s in each file.
+    defines: HashMap<Module, Vec<Define>>,
+    /// The source of each file with its includes spliced in and its comments stripped, to find the shader defs that it
+    /// refers to.
+    sources: HashMap<Module, String>,
+    /// The live `#export`s in each file.
+    re_exports: ReExports,
+    prelude: Prelude,
 }
 
 impl ImportOrder {
-    /// Given a root module, traverses the file system to find all imports
+    /// Given a root module, traverses the file system to find all imports which are live with the given shader defs,
+    /// including the imports of the configured prelude.
     pub(crate) fn calculate(
         absolute_source_path: AbsoluteWGSLFilePathBuf,
-        source_root: Option<&AbsoluteRustRootPathBuf>,
+        search_paths: &SearchPaths,
+        shader_defs: &HashMap<String, ShaderDefValue>,
+        prelude_imports: &[String],
     ) -> Result<Self, ImportResolutionError> {
         let root_import = Module::from_path(absolute_source_path);
+        let mut prelude = Prelude::resolve(prelude_imports, &root_import, search_paths)?;
+        prelude.exempt(prelude_dependencies(&prelude, search_paths, shader_defs)?);
 
-        let mut order = daggy::Dag::<Module, ()>::new();
+        let mut order = daggy::Dag::<Module, ImportSite>::new();
         let mut nodes = HashMap::new();
+        let mut defines = HashMap::new();
+        let mut sources = HashMap::new();
+        let mut re_exports = HashMap::new();
 
-        // Follow a DFS over imports, detecting cycles using daggy.
-        let mut search_front =
-            std::collections::VecDeque::from(vec![(Option::<Module>::None, root_import.clone())]);
-        while let Some((importing_path, imported)) = search_front.pop_front() {
+        // Follow a DFS over imports, detecting cycles using daggy. Each file is searched with the shader defs in scope
+        // where it was imported, which include the `#define`s of the files importing it.
+        let mut search_front = std::collections::VecDeque::from(vec![(
+            Option::<(Module, ImportSite)>::None,
+            root_import.clone(),
+            shader_defs.clone(),
+        )]);
+        while let Some((importing, imported, shader_defs)) = search_front.pop_front() {
             // If we haven't seen the dependency before, add it to the record
             let imported_node = match nodes.get(&imported) {
                 None => {
@@ -159,12 +830,12 @@ impl ImportOrder {
             };
 
             // If it was imported by a file, add an import edge
-            if let Some(importing_path) = importing_path {
+            if let Some((importing_path, site)) = importing {
                 let importing_node = *nodes
                     .get(&importing_path)
                     .expect("importees should always be added before their imports");
 
-                let res = order.add_edge(importing_node, imported_node, ());
+                let res = order.add_edge(importing_node, imported_node, site);
                 if res.is_err() {
                     // Cycle on imports
                     let cycle_path = find_any_path(&order, imported_node, importing_node);
@@ -177,27 +848,270 @@ impl ImportOrder {
             }
 
             // Then add the imports requested by this file
-            let source = imported.read_to_string();
-            for requested in all_imports_in_source(&source) {
-                match Module::resolve_module(&imported, source_root, requested) {
-                    Ok(import) => search_front.push_back((Some(imported.clone()), import)),
-                    Err(err) => {
-                        return Err(ImportResolutionError::Unresolved {
-                            requested: requested.to_owned(),
-                            importer: imported,
-                            searched: err.into_iter().collect(),
-                        });
-                    }
+            let spliced = imported
+                .read_spliced(&shader_defs)
+                .map_err(|message| ImportResolutionError::Include { message })?;
+            let spliced = prelude.apply(&imported, spliced);
+            let source = spliced.source.as_str();
+
+            // Finds where a line of the source was written. The prelude's imports are added before the start of the file
+            let site = |line: usize| match spliced.origin(line) {
+                Some((file, file_line)) => ImportSite {
+                    file: file.clone(),
+                    line: Some(file_line),
+                },
+                None => ImportSite {
+                    file: imported.path(),
+                    line: None,
+                },
+            };
+            let condition_error = |error: ConditionError| ImportResolutionError::Condition {
+                site: site(error.line),
+                message: error.message,
+            };
+
+            let file_defines =
+                conditionals::live_defines(source, &shader_defs).map_err(condition_error)?;
+            let mut import_defs = shader_defs.clone();
+            import_defs.extend(
+                file_defines
+                    .iter()
+                    .map(|define| (define.name.clone(), define.value)),
+            );
+            let imports = all_imports_in_source(source, &shader_defs).map_err(condition_error)?;
+            for (requested, params, line) in imports {
+                let site = site(line);
+                for import in resolve_instances(&imported, search_paths, requested, params)? {
+                    let mut import_defs = import_defs.clone();
+                    import_defs.extend(import.params().iter().cloned());
+                    search_front.push_back((
+                        Some((imported.clone(), site.clone())),
+                        import,
+                        import_defs,
+                    ));
                 }
             }
+            let mut file_re_exports = Vec::new();
+            let exports = all_exports_in_source(source, &shader_defs).map_err(condition_error)?;
+            for (requested, params, tail) in exports {
+                let items = import_items(tail);
+                file_re_exports.push(ReExport {
+                    modules: resolve_instances(&imported, search_paths, requested, params)?,
+                    items: (!items.is_empty()).then(|| {
+                        items
+                            .iter()
+                            .map(|item| (item.name.to_owned(), item.alias.map(str::to_owned)))
+                            .collect()
+                    }),
+                });
+            }
+            re_exports
+                .entry(imported.clone())
+                .or_insert(file_re_exports);
+            sources
+                .entry(imported.clone())
+                .or_insert_with(|| lexer::strip_comments(source));
+            defines.entry(imported).or_insert(file_defines);
         }
 
         Ok(ImportOrder {
             dag: order,
             node_of_interest: nodes[&root_import],
+            defines,
+            sources,
+            re_exports,
+            prelude,
         })
     }
 
+    /// Gives the shader defs that each imported file should be composed with. These are the shader defs given to the
+    /// shader, along with every `#define` in the root file, in the file itself and in every file which imports it,
+    /// directly or indirectly. Gives an error for every `#define` which gives a shader def a different value to the one
+    /// in scope. Each file is only composed once, so also gives an error for every shader def which the file, or any
+    /// file that it imports, refers to but which has different values in the files importing it. The parameters that a
+    /// file is instantiated with override all of these.
+    pub(crate) fn module_defs(
+        &self,
+        shader_defs: &HashMap<String, ShaderDefValue>,
+    ) -> Result<HashMap<Module, HashMap<String, ShaderDefValue>>, Vec<String>> {
+        let mut errors = Vec::new();
+        let mut push_error = |error: String| {
+            if !errors.contains(&error) {
+                errors.push(error);
+            }
+        };
+
+        let graph = self.dag.graph();
+        let order = daggy::petgraph::algo::toposort(graph, None).expect("imports are acyclic");
+        let importers_of = |node| {
+            let mut importers = graph
+                .neighbors_directed(node, daggy::petgraph::Direction::Incoming)
+                .collect::<Vec<_>>();
+            importers.sort();
+            importers.dedup();
+            importers
+        };
+
+        // The shader defs that each file, or any file that it imports, refers to. A file which defines a shader def
+        // itself decides its value for the files it imports
+        let def_names = shader_defs
+            .keys()
+            .chain(self.defines.values().flatten().map(|define| &define.name))
+            .collect::<HashSet<_>>();
+        let mut referenced = HashMap::<daggy::NodeIndex, HashSet<String>>::new();
+        for &node in order.iter().rev() {
+            let module = &self.dag[node];
+            let source = self.sources.get(module).map_or("", String::as_str);
+            let mut names = def_names
+                .iter()
+                .filter(|name| refers_to(source, name))
+                .map(|name| name.to_string())
+                .collect::<HashSet<_>>();
+            for imported in graph.neighbors(node) {
+                names.extend(referenced[&imported].iter().cloned());
+            }
+            for define in self.defines.get(module).into_iter().flatten() {
+                names.remove(&define.name);
+            }
+            referenced.insert(node, names);
+        }
+
+        // The shader defs in scope in each file, along with where each was defined
+        let mut scoped =
+            HashMap::<daggy::NodeIndex, HashMap<String, (ShaderDefValue, String)>>::new();
+        for &node in &order {
+            let module = &self.dag[node];
+            let importers = importers_of(node);
+            let mut defs = HashMap::new();
+            if node == self.node_of_interest {
+                defs.extend(shader_defs.iter().map(|(name, value)| {
+                    (
+                        name.clone(),
+                        (*value, "the defs given to the macro".to_owned()),
+                    )
+                }));
+            }
+            for importer in &importers {
+                for (name, def) in &scoped[importer] {
+                    defs.entry(name.clone()).or_insert_with(|| def.clone());
+                }
+            }
+
+            let mut names = referenced[&node].iter().collect::<Vec<_>>();
+            names.sort();
+            for name in names {
+                let Some((first, rest)) = importers.split_first() else {
+                    continue;
+                };
+                let first_def = scoped[first].get(name);
+                for other in rest {
+                    let other_def = scoped[other].get(name);
+                    if first_def.map(|(value, _)| value) == other_def.map(|(value, _)| value) {
+                        continue;
+                    }
+                    let describe = |def: Option<&(ShaderDefValue, String)>,
+                                    importer: daggy::NodeIndex| {
+                        match def {
+                            Some((value, site)) => format!(
+                                "is `{}` where it is imported by `{}`, defined by {}",
+                                conditionals::display_value(value),
+                                self.dag[importer],
+                                site
+                            ),
+                            None => format!(
+                                "is not defined where it is imported by `{}`",
+                                self.dag[importer]
+                            ),
+                        }
+                    };
+                    push_error(format!(
+                        "`{}` is only composed once, but is imported with different values of shader def `{}`, \
+                        which it refers to: `{}` {}, but {}",
+                        module,
+                        name,
+                        name,
+                        describe(first_def, *first),
+                        describe(other_def, *other)
+                    ));
+                }
+            }
+
+            for define in self.defines.get(module).into_iter().flatten() {
+                let site = format!("`#define` on line {} of `{}`", define.line, module);
+                match defs.get(&define.name) {
+                    Some((value, other_site)) if *value != define.value => {
+                        push_error(format!(
+                            "shader def `{}` is defined as `{}` by {}, but as `{}` by {}",
+                            define.name,
+                            conditionals::display_value(value),
+                            other_site,
+                            conditionals::display_value(&define.value),
+                            site
+                        ));
+                    }
+                    Some(_) => {}
+                    None => {
+                        defs.insert(define.name.clone(), (define.value, site));
+                    }
+                }
+            }
+            scoped.insert(node, defs);
+        }
+
+        let module_defs = self
+            .dag
+            .node_references()
+            .filter(|(node, _)| *node != self.node_of_interest)
+            .map(|(node, module)| {
+                let mut defs = scoped[&node]
+                    .iter()
+                    .map(|(name, (value, _))| (name.clone(), *value))
+                    .collect::<HashMap<_, _>>();
+                defs.extend(module.params().iter().cloned());
+                (module.clone(), defs)
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(module_defs)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Gives the items that each module re-exports with `#export`.
+    pub(crate) fn re_exports(&self) -> ReExports {
+        self.re_exports.clone()
+    }
+
+    /// Gives the prelude that was added to each module's imports.
+    pub(crate) fn prelude(&self) -> Prelude {
+        self.prelude.clone()
+    }
+
+    /// Gives the chain of imports that each imported module was reached through from the root module, e.g.
+    /// `` `lighting.wgsl:3` → `main.wgsl:2` ``, starting with the import of the module itself.
+    pub(crate) fn import_chains(&self) -> HashMap<Module, String> {
+        let mut chains = HashMap::new();
+        for (node, module) in self.dag.node_references() {
+            if node == self.node_of_interest {
+                continue;
+            }
+
+            let path = find_any_path(&self.dag, self.node_of_interest, node);
+            let chain = path
+                .windows(2)
+                .rev()
+                .filter_map(|pair| {
+                    let edge = self.dag.find_edge(pair[0], pair[1])?;
+                    Some(self.dag[edge].to_string())
+                })
+                .collect::<Vec<_>>()
+                .join(" → ");
+            chains.insert(module.clone(), chain);
+        }
+        chains
+    }
+
     /// Gives a vector of every node that needs to be imported, in order of import from leaf to the node of interest.
     /// The root node is excluded from the import order.
     fn import_order(mut self) -> Vec<Module> {
@@ -234,10 +1148,25 @@ impl ImportOrder {
         let mut forwards = HashMap::new();
         let mut backwards = HashMap::new();
 
+        // Files which declare a logical name with `#define_import_path` are imported by that name
+        let mut logical_names = HashSet::new();
+        for (_, import) in self.dag.node_references() {
+            if let Some(logical_name) = import.logical_name() {
+                forwards.insert(import.clone(), logical_name.clone());
+                logical_names.insert(logical_name);
+            }
+        }
+
         // Assign names by increasing the amount of the path present until distinguished
         // First assign each path just its suffix, without the extension
         for (_, import) in self.dag.node_references() {
-            let file_name = import.file_name().to_string();
+            if forwards.contains_key(import) {
+                continue;
+            }
+            let mut file_name = import.file_name().to_string();
+            while logical_names.contains(&file_name) {
+                file_name.push('_');
+            }
 
             forwards.insert(import.clone(), file_name.clone());
             backwards
@@ -258,11 +1187,15 @@ impl ImportOrder {
             for (i, (path_size, import)) in collisions.into_iter().enumerate() {
                 forwards.remove(&import);
 
-                let new_name = if let Some(extra_component) = import.nth_path_component(path_size) {
-                    colliding_name.clone() + "_" + &extra_component
-                } else {
-                    colliding_name.clone() + &format!("{}", i)
-                };
+                let mut new_name =
+                    if let Some(extra_component) = import.nth_path_component(path_size) {
+                        colliding_name.clone() + "_" + &extra_component
+                    } else {
+                        colliding_name.clone() + &format!("{}", i)
+                    };
+                while logical_names.contains(&new_name) {
+                    new_name.push('_');
+                }
 
                 forwards.insert(import.clone(), new_name.clone());
                 backwards
@@ -284,3 +1217,200 @@ impl ImportOrder {
         (imports, root)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::test_shaders;
+
+    #[test]
+    fn embedded_arrays_are_declared() {
+        let module = Module::from_path(test_shaders::shader("embed/table.wgsl"));
+        let found = resolve_item(&module, "TABLE", &ReExports::new(), &HashMap::new());
+        assert_eq!(found.map(|(_, name)| name), Some("TABLE".to_owned()));
+    }
+
+    #[test]
+    fn suggests_close_item_names() {
+        let main = Module::from_path(test_shaders::shader("suggestions/nested/main.wgsl"));
+        let utils = Module::from_path(test_shaders::shader("suggestions/utils.wgsl"));
+        let module_names = HashMap::from([(utils.clone(), "utils".to_owned())]);
+
+        let errors = replace_imports_in_source(
+            "#import ../utils.wgsl::helpr\n",
+            &main,
+            &SearchPaths::new(None, Vec::new()),
+            &module_names,
+            &ReExports::new(),
+            &HashMap::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            [format!(
+                "`{}` does not declare or re-export `helpr`, which is imported by `{}` with \
+                `#import ../utils.wgsl::helpr`\nhelp: did you mean `helper`?",
+                utils, main
+            )]
+        );
+    }
+
+    #[test]
+    fn instantiates_files_with_params() {
+        let main = Module::from_path(test_shaders::shader("params/main.wgsl"));
+        let search_paths = SearchPaths::new(None, Vec::new());
+
+        let blur = resolve_instances(
+            &main,
+            &search_paths,
+            "blur.wgsl",
+            Some("RADIUS = 9, HORIZONTAL"),
+        )
+        .map_err(|error| error.to_string())
+        .unwrap();
+        assert_eq!(
+            blur.iter().map(Module::file_name).collect::<Vec<_>>(),
+            ["blur_horizontal_true_radius_9"]
+        );
+
+        // The files that an instance imports are instantiated with the same parameters
+        let kernel = resolve_instances(&blur[0], &search_paths, "kernel.wgsl", None)
+            .map_err(|error| error.to_string())
+            .unwrap();
+        assert_eq!(kernel[0].params(), blur[0].params());
+        assert_eq!(kernel[0].file_name(), "kernel_horizontal_true_radius_9");
+    }
+
+    #[test]
+    fn rejects_params_for_shared_files() {
+        let main = Module::from_path(test_shaders::shader("params/main.wgsl"));
+        let search_paths = SearchPaths::new(None, Vec::new());
+
+        let Err(error) = resolve_instances(&main, &search_paths, "*.wgsl", Some("RADIUS = 5"))
+        else {
+            panic!("a glob import is given parameters");
+        };
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "could not resolve import `*.wgsl` in file `{}`: only imports of a single `.wgsl` file can be \
+                given parameters with `with (...)`",
+                main
+            )
+        );
+    }
+
+    fn import_order(path: &str) -> Result<ImportOrder, String> {
+        ImportOrder::calculate(
+            test_shaders::shader(path),
+            &SearchPaths::new(None, Vec::new()),
+            &HashMap::new(),
+            &[],
+        )
+        .map_err(|error| error.to_string())
+    }
+
+    fn module_defs(
+        path: &str,
+    ) -> Result<HashMap<Module, HashMap<String, ShaderDefValue>>, Vec<String>> {
+        import_order(path)
+            .map_err(|error| vec![error])?
+            .module_defs(&HashMap::new())
+    }
+
+    #[test]
+    fn traces_import_chains() {
+        let Ok(order) = import_order("import_chains/main.wgsl") else {
+            panic!("the import order can be calculated");
+        };
+        let main = test_shaders::shader("import_chains/main.wgsl");
+        let middle = test_shaders::shader("import_chains/middle.wgsl");
+        let leaf = test_shaders::shader("import_chains/leaf.wgsl");
+        let chains = order.import_chains();
+        assert_eq!(chains.len(), 2);
+        assert_eq!(
+            chains[&Module::from_path(middle.clone())],
+            format!("`{}:1`", main.display())
+        );
+        assert_eq!(
+            chains[&Module::from_path(leaf)],
+            format!("`{}:2` → `{}:1`", middle.display(), main.display())
+        );
+    }
+
+    #[test]
+    fn follows_re_exports() {
+        let Ok(order) = import_order("reexports/main.wgsl") else {
+            panic!("the import order can be calculated");
+        };
+        let prelude = Module::from_path(test_shaders::shader("reexports/prelude.wgsl"));
+        let re_exports = order.re_exports();
+        let found = |name| {
+            resolve_item(&prelude, name, &re_exports, &HashMap::new())
+                .map(|(module, name)| (module.file_name(), name))
+        };
+        assert_eq!(
+            found("helper"),
+            Some(("utils".to_owned(), "helper".to_owned()))
+        );
+        assert_eq!(
+            found("apply_light"),
+            Some(("lighting".to_owned(), "light".to_owned()))
+        );
+        assert_eq!(found("light"), None);
+    }
+
+    #[test]
+    fn reports_re_export_cycles() {
+        let Err(error) = import_order("reexports/cycle.wgsl") else {
+            panic!("the cycle is found");
+        };
+        let cycle = Module::from_path(test_shaders::shader("reexports/cycle.wgsl"));
+        let utils = Module::from_path(test_shaders::shader("reexports/cycle_utils.wgsl"));
+        assert_eq!(
+            error,
+            format!(
+                "found import cycle:\n`{}` ->\n`{}` ->\n`{}`",
+                cycle, utils, cycle
+            )
+        );
+    }
+
+    #[test]
+    fn scopes_defines_to_imported_files() {
+        let module_defs = module_defs("scoped_defines/local.wgsl").unwrap();
+        let scaled = Module::from_path(test_shaders::shader("scoped_defines/scaled.wgsl"));
+        let plain = Module::from_path(test_shaders::shader("scoped_defines/plain.wgsl"));
+        assert_eq!(
+            module_defs[&scaled].get("DOUBLED"),
+            Some(&ShaderDefValue::Bool(true))
+        );
+        assert_eq!(module_defs[&plain].get("DOUBLED"), None);
+    }
+
+    #[test]
+    fn reports_files_imported_with_different_defs() {
+        let Err(errors) = module_defs("scoped_defines/conflicting.wgsl") else {
+            panic!("`common.wgsl` is composed with different defs");
+        };
+        let module = |path| Module::from_path(test_shaders::shader(path));
+        let common = module("scoped_defines/common.wgsl");
+        let quality = module("scoped_defines/quality.wgsl");
+        let plain = module("scoped_defines/plain.wgsl");
+        assert_eq!(
+            errors,
+            [format!(
+                "`{}` is only composed once, but is imported with different values of shader def `QUALITY`, \
+                which it refers to: `QUALITY` is `2u` where it is imported by `{}`, defined by `#define` on line 1 \
+                of `{}`, but is not defined where it is imported by `{}`",
+                common, quality, quality, plain
+            )]
+        );
+    }
+
+    #[test]
+    fn ignores_commented_out_declarations() {
+        let source = "// fn old() {}\n/* struct Old {} */\nfn new() { const local = 1; }\nstruct Light { color: vec3<f32> }\n";
+        assert_eq!(declared_items(source, &HashMap::new()), ["new", "Light"]);
+    }
+}
@@ -0,0 +1,288 @@
+use std::{collections::HashMap, ffi::OsStr};
+
+use naga_oil::compose::ShaderDefValue;
+
+use crate::{
+    conditionals,
+    files::{self, AbsoluteWGSLFilePathBuf},
+    lexer::Directive,
+};
+
+/// Where a run of lines in a spliced source came from.
+#[derive(Debug, Clone)]
+struct Segment {
+    /// The first line of the run in the spliced source, starting from 1.
+    first_line: usize,
+    file: AbsoluteWGSLFilePathBuf,
+    /// The first line of the run in `file`, starting from 1.
+    file_first_line: usize,
+}
+
+/// Finds the file given to an `#include`, relative to the including file.
+fn include_path(
+    including: &AbsoluteWGSLFilePathBuf,
+    args: &str,
+) -> Result<AbsoluteWGSLFilePathBuf, String> {
+    let Some(requested) = args
+        .strip_prefix('"')
+        .and_then(|args| args.strip_suffix('"'))
+    else {
+        return Err(format!(
+            "`#include {}` in file `{}` should give a quoted path, e.g. `#include \"fragment.wgsl\"`",
+            args,
+            including.display()
+        ));
+    };
+
+    let expanded = files::expand_env_vars(requested).map_err(|variable| {
+        format!(
+            "could not include `{}` in file `{}`: environment variable `{}` is not set",
+            requested,
+            including.display(),
+            variable
+        )
+    })?;
+    let path = including
+        .parent()
+        .expect("files have parent directories")
+        .join(expanded);
+    if !path.is_file() {
+        return Err(format!(
+            "could not include `{}` in file `{}`: `{}` does not exist",
+            requested,
+            including.display(),
+            path.display()
+        ));
+    }
+    if path.extension() != Some(OsStr::new("wgsl")) {
+        return Err(format!(
+            "could not include `{}` in file `{}`: included files must have the `.wgsl` extension",
+            requested,
+            including.display()
+        ));
+    }
+
+    let path = path.canonicalize().map_err(|e| {
+        format!(
+            "could not include `{}` in file `{}`: {}",
+            requested,
+            including.display(),
+            e
+        )
+    })?;
+    AbsoluteWGSLFilePathBuf::new(path)
+}
+
+/// A shader's source with each `#include` replaced by the contents of the file that it includes, along with where each
+/// line of it came from.
+#[derive(Debug, Clone)]
+pub(crate) struct SplicedSource {
+    pub(crate) source: String,
+    segments: Vec<Segment>,
+    /// The number of lines added before the first line of the file, such as the prelude's imports.
+    added_lines: usize,
+}
+
+impl SplicedSource {
+    /// Reads a file, splicing in the files that it includes, and the files that they include. Only the `#include`s which
+    /// are live with the given shader defs are spliced in.
+    pub(crate) fn read(
+        path: &AbsoluteWGSLFilePathBuf,
+        shader_defs: &HashMap<String, ShaderDefValue>,
+    ) -> Result<Self, String> {
+        Self::read_within(path, shader_defs, &mut Vec::new())
+    }
+
+    /// Reads a file which is included by each of `including`, in order.
+    fn read_within(
+        path: &AbsoluteWGSLFilePathBuf,
+        shader_defs: &HashMap<String, ShaderDefValue>,
+        including: &mut Vec<AbsoluteWGSLFilePathBuf>,
+    ) -> Result<Self, String> {
+        if let Some(start) = including.iter().position(|file| file == path) {
+            let mut message = "found include cycle:\n".to_owned();
+            for file in &including[start..] {
+                message += &format!("`{}` ->\n", file.display());
+            }
+            message += &format!("`{}`", path.display());
+            return Err(message);
+        }
+
+        let source = std::fs::read_to_string(&**path)
+            .map_err(|e| format!("could not read `{}`: {}", path.display(), e))?;
+        let mut spliced = Self {
+            source: String::new(),
+            segments: vec![Segment {
+                first_line: 1,
+                file: path.clone(),
+                file_first_line: 1,
+            }],
+            added_lines: 0,
+        };
+
+        including.push(path.clone());
+        let mut last = 0;
+        let directives = conditionals::live_directives(&source, shader_defs)
+            .map_err(|error| format!("in file `{}`: {}", path.display(), error))?;
+        for directive in directives {
+            let Directive::Other {
+                name: "include",
+                args,
+                span,
+            } = directive
+            else {
+                continue;
+            };
+
+            let included = Self::read_within(&include_path(path, args)?, shader_defs, including)?;
+            spliced.source.push_str(&source[last..span.start]);
+            let line = spliced.current_line();
+            spliced
+                .segments
+                .extend(included.segments.into_iter().map(|segment| Segment {
+                    first_line: line + segment.first_line - 1,
+                    ..segment
+                }));
+            spliced.source.push_str(&included.source);
+
+            // The rest of the line with the `#include` is empty, so the including file resumes on the next line
+            spliced.segments.push(Segment {
+                first_line: spliced.current_line() + 1,
+                file: path.clone(),
+                file_first_line: source[..span.end].matches('\n').count() + 2,
+            });
+            last = span.end;
+        }
+        including.pop();
+        spliced.source.push_str(&source[last..]);
+
+        Ok(spliced)
+    }
+
+    /// The line of the spliced source that the end of it is on, starting from 1.
+    fn current_line(&self) -> usize {
+        self.source.matches('\n').count() + 1
+    }
+
+    /// Adds lines before the first line of the file, which don't come from any file. `lines` must end with a newline.
+    pub(crate) fn prepend_lines(&mut self, lines: &str) {
+        self.source.insert_str(0, lines);
+        self.added_lines += lines.matches('\n').count();
+    }
+
+    /// Whether any files were spliced in.
+    pub(crate) fn has_includes(&self) -> bool {
+        self.segments.len() > 1
+    }
+
+    /// Whether any line of the source is on a different line of the file that it came from.
+    pub(crate) fn moves_lines(&self) -> bool {
+        self.has_includes() || self.added_lines > 0
+    }
+
+    /// Every file that was spliced in, directly or indirectly.
+    pub(crate) fn included_files(&self) -> impl Iterator<Item = &AbsoluteWGSLFilePathBuf> {
+        let root = &self.segments[0].file;
+        self.segments
+            .iter()
+            .map(|segment| &segment.file)
+            .filter(move |file| *file != root)
+    }
+
+    /// Finds the file and line, starting from 1, that a line of the spliced source came from, or `None` if the line was
+    /// added before the start of the file.
+    pub(crate) fn origin(&self, line: usize) -> Option<(&AbsoluteWGSLFilePathBuf, usize)> {
+        let line = line
+            .checked_sub(self.added_lines)
+            .filter(|line| *line > 0)?;
+        let segment = self
+            .segments
+            .iter()
+            .rev()
+            .find(|segment| segment.first_line <= line)
+            .expect("the first segment starts on the first line");
+        Some((
+            &segment.file,
+            segment.file_first_line + line - segment.first_line,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::test_shaders;
+
+    #[test]
+    fn splices_nested_includes() {
+        let main = test_shaders::shader("includes/main.wgsl");
+        let spliced = SplicedSource::read(&main, &HashMap::new()).unwrap();
+        assert_eq!(
+            spliced.source,
+            "// line 1\nfn first() {}\nfn second() {}\n\nfn after_second() {}\n\nfn main_fn() {}\n\
+            #ifdef MISSING_PART\n#include \"missing.wgsl\"\n#endif\n"
+        );
+
+        let first = test_shaders::shader("includes/parts/first.wgsl");
+        let second = test_shaders::shader("includes/parts/second.wgsl");
+        let origin = |line| {
+            let (file, line) = spliced.origin(line).unwrap();
+            (file.clone(), line)
+        };
+        assert_eq!(origin(1), (main.clone(), 1));
+        assert_eq!(origin(2), (first.clone(), 1));
+        assert_eq!(origin(3), (second.clone(), 1));
+        assert_eq!(origin(5), (first.clone(), 3));
+        assert_eq!(origin(7), (main.clone(), 3));
+        assert_eq!(origin(8), (main, 4));
+
+        assert!(spliced.has_includes());
+        assert!(spliced.moves_lines());
+        assert!(spliced.included_files().any(|file| *file == first));
+        assert!(spliced.included_files().any(|file| *file == second));
+    }
+
+    #[test]
+    fn maps_lines_after_prepended_lines() {
+        let second = test_shaders::shader("includes/parts/second.wgsl");
+        let mut spliced = SplicedSource::read(&second, &HashMap::new()).unwrap();
+        assert!(!spliced.moves_lines());
+
+        spliced.prepend_lines("#import a.wgsl\n#import b.wgsl\n");
+        assert!(spliced.moves_lines());
+        assert_eq!(spliced.origin(1), None);
+        assert_eq!(spliced.origin(2), None);
+        assert_eq!(spliced.origin(3), Some((&second, 1)));
+    }
+
+    #[test]
+    fn only_splices_live_includes() {
+        let main = test_shaders::shader("includes/main.wgsl");
+        let shader_defs = HashMap::from([("MISSING_PART".to_owned(), ShaderDefValue::Bool(true))]);
+        let error = SplicedSource::read(&main, &shader_defs).unwrap_err();
+        assert_eq!(
+            error,
+            format!(
+                "could not include `missing.wgsl` in file `{}`: `{}` does not exist",
+                main.display(),
+                main.parent().unwrap().join("missing.wgsl").display()
+            )
+        );
+    }
+
+    #[test]
+    fn reports_include_cycles() {
+        let cycle_a = test_shaders::shader("includes/cycle_a.wgsl");
+        let cycle_b = test_shaders::shader("includes/cycle_b.wgsl");
+        let error = SplicedSource::read(&cycle_a, &HashMap::new()).unwrap_err();
+        assert_eq!(
+            error,
+            format!(
+                "found include cycle:\n`{}` ->\n`{}` ->\n`{}`",
+                cycle_a.display(),
+                cycle_b.display(),
+                cycle_a.display()
+            )
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use crate::files;
+
+/// A Rust file within a crate, along with its contents as of the last time that it was read.
+struct IndexedFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    contents: String,
+}
+
+impl IndexedFile {
+    fn new(path: PathBuf) -> Self {
+        let mut file = Self {
+            path,
+            modified: None,
+            contents: String::new(),
+        };
+        file.refresh();
+        file
+    }
+
+    /// Re-reads the file if it has changed since it was last read.
+    fn refresh(&mut self) {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified.is_some() && modified == self.modified {
+            return;
+        }
+
+        self.modified = modified;
+        self.contents = std::fs::read_to_string(&self.path).unwrap_or_default();
+    }
+}
+
+lazy_static::lazy_static! {
+    /// Every Rust file found in each crate that has been searched, so that each invocation of the macro doesn't walk the whole crate.
+    static ref RUST_FILE_INDEX: Mutex<HashMap<PathBuf, Vec<IndexedFile>>> = Mutex::new(HashMap::new());
+}
+
+/// Finds every Rust file in a folder, skipping hidden folders and build output folders, which contain copies of sources.
+fn collect_rust_files(root: &Path, folder: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(folder) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            let is_hidden = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            let is_build_output =
+                path == root.join("target") || path.join("CACHEDIR.TAG").is_file();
+            if !is_hidden && !is_build_output {
+                collect_rust_files(root, &path, files);
+            }
+        } else if path.extension() == Some(OsStr::new("rs")) {
+            files.push(path);
+        }
+    }
+}
+
+/// Finds the files in a crate which contain the given text, using the cached index of the crate's files
+/// and only re-walking the crate if nothing is found, in case the invoking file is new.
+fn find_by_contents(root: &Path, pattern: &str) -> Vec<PathBuf> {
+    let mut index = RUST_FILE_INDEX
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let mut rebuilt = !index.contains_key(root);
+
+    loop {
+        let files = index.entry(root.to_path_buf()).or_insert_with(|| {
+            let mut paths = Vec::new();
+            collect_rust_files(root, root, &mut paths);
+            paths.into_iter().map(IndexedFile::new).collect()
+        });
+
+        let mut options = Vec::new();
+        for file in files.iter_mut() {
+            file.refresh();
+            if file.contents.contains(pattern) {
+                options.push(file.path.clone());
+            }
+        }
+
+        if !options.is_empty() || rebuilt {
+            return options;
+        }
+
+        index.remove(root);
+        rebuilt = true;
+    }
+}
+
+/// Finds the Rust file that an invocation of the macro was written in, given the path literal passed to the macro.
+///
+/// Where the compiler supports it, this uses the span of the literal. Otherwise falls back to searching the
+/// crate for the literal, which requires that the literal is unique and is written in the crate's source text.
+pub(crate) fn find_invocation_file(path_literal: &syn::LitStr) -> Result<PathBuf, String> {
+    #[cfg(span_local_file)]
+    if let Some(path) = path_literal.span().unwrap().local_file() {
+        // Relative paths are relative to the working directory of the compiler
+        let path = match std::env::current_dir() {
+            Ok(working_dir) => working_dir.join(path),
+            Err(_) => path,
+        };
+        if path.is_file() {
+            return Ok(path);
+        }
+    }
+
+    let root = files::manifest_dir()?;
+    let options = find_by_contents(&root, &format!("\"{}\"", path_literal.value()));
+
+    match options.as_slice() {
+        [] => Err(
+            "could not find invocation point - maybe it was in a macro? This won't be an issue when \
+            using Rust 1.88 or later, but until then each instance of the `include_wgsl_oil` \
+            must be present in the source text, and each must have a unique argument."
+                .to_owned(),
+        ),
+        [v] => Ok(v.clone()),
+        _ => Err(format!(
+            "found more than one contender for macro invocation location. \
+            This won't be an issue when using Rust 1.88 or later, but until then each instance \
+            of the `include_wgsl_oil` must be present in the source text, and each must have a unique argument. \
+            found locations: {:?}",
+            options
+                .into_iter()
+                .map(|path| format!("`{}`", path.display()))
+                .collect::<Vec<String>>()
+        )),
+    }
+}
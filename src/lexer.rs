@@ -0,0 +1,449 @@
+use std::ops::Range;
+
+/// A preprocessor directive or attribute found in a shader's source, outside of any comments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Directive<'a> {
+    /// An import, e.g. `#import utils.wgsl as Utils` or `#import my_lib::lighting::{light}`, or a re-export, e.g.
+    /// `#export utils.wgsl::{MyStruct, my_fn}`.
+    Import {
+        /// The whole directive, from the `#` to the end of the last line of code that it covers.
+        span: Range<usize>,
+        /// The path or logical name imported, e.g. `utils.wgsl` or `my_lib::lighting`.
+        path: &'a str,
+        path_span: Range<usize>,
+        /// Everything following the path, e.g. `as Utils` or `::{light}`, excluding any parameters.
+        tail: &'a str,
+        /// The shader defs given in a `with (...)` clause, e.g. `RADIUS = 5` in `#import blur.wgsl as Blur with (RADIUS = 5)`.
+        params: Option<&'a str>,
+        /// The whole `with (...)` clause.
+        params_span: Option<Range<usize>>,
+        /// Whether the items imported are re-exported to the files importing this one, i.e. the directive is `#export`.
+        reexport: bool,
+    },
+    /// A declaration of the logical name of a file, e.g. `#define_import_path my_lib::lighting`.
+    DefineImportPath { span: Range<usize>, name: &'a str },
+    /// Any other preprocessor directive, e.g. `#ifdef SHADOWS` or `#define SAMPLES 4`.
+    Other {
+        span: Range<usize>,
+        name: &'a str,
+        /// Everything following the name of the directive.
+        args: &'a str,
+    },
+    /// An `@export` attribute on a struct, e.g. `@export struct Light { ... }`.
+    ExportStruct {
+        /// The `@export` attribute.
+        span: Range<usize>,
+        struct_name: &'a str,
+    },
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Gives the length of the comment starting at the start of `source`, if there is one. Block comments may be nested.
+fn comment_len(source: &str) -> Option<usize> {
+    if source.starts_with("//") {
+        return Some(source.find('\n').unwrap_or(source.len()));
+    }
+    if !source.starts_with("/*") {
+        return None;
+    }
+
+    let mut depth = 0;
+    let mut i = 0;
+    while i < source.len() {
+        let rest = &source[i..];
+        if rest.starts_with("/*") {
+            depth += 1;
+            i += 2;
+        } else if rest.starts_with("*/") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return Some(i);
+            }
+        } else {
+            i += rest.chars().next().expect("not at end").len_utf8();
+        }
+    }
+    Some(source.len())
+}
+
+/// Gives the length of the quoted string starting at the start of `source`, if there is one, e.g. a path given to
+/// `#include`. Strings end at the closing quote or the end of the line.
+fn string_len(source: &str) -> Option<usize> {
+    let rest = source.strip_prefix('"')?;
+    let len = rest.find(['"', '\n']).map_or(rest.len(), |len| {
+        len + usize::from(rest[len..].starts_with('"'))
+    });
+    Some(1 + len)
+}
+
+/// Finds the end of the code in a directive starting at `start`, which is the end of the line unless a brace is left open,
+/// in which case the directive continues over the following lines until the brace is closed. Comments are skipped, and
+/// those within the directive are recorded in `comments`.
+fn directive_end(source: &str, start: usize, comments: &mut Vec<Range<usize>>) -> usize {
+    let mut depth = 0usize;
+    let mut end = start;
+    let mut i = start;
+    while i < source.len() {
+        let rest = &source[i..];
+        if let Some(len) = comment_len(rest) {
+            comments.push(i..i + len);
+            i += len;
+            continue;
+        }
+        if let Some(len) = string_len(rest) {
+            i += len;
+            end = i;
+            continue;
+        }
+
+        let c = rest.chars().next().expect("not at end");
+        match c {
+            '\n' if depth == 0 => break,
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        if !c.is_whitespace() {
+            end = i + c.len_utf8();
+        }
+        i += c.len_utf8();
+    }
+
+    // Comments after the end of the directive are found again once it has been read
+    comments.retain(|comment| comment.start < end);
+    end
+}
+
+/// Gives the length of the identifier at the start of `source`.
+fn ident_len(source: &str) -> usize {
+    source
+        .find(|c: char| !is_ident_char(c))
+        .unwrap_or(source.len())
+}
+
+/// Finds the path given to an import, which is either a file path ending in `.wgsl`, or a logical name made of
+/// identifiers separated by `::`. A path can be written within double quotes so that it can contain whitespace, e.g.
+/// `"my shaders/utils.wgsl"`. Gives the path and the length of the text that it was written as.
+fn import_path(args: &str) -> Option<(&str, usize)> {
+    if let Some(quoted) = args.strip_prefix('"') {
+        let len = quoted.find('"')?;
+        return Some((&quoted[..len], len + 2));
+    }
+
+    let token_len = args.find(char::is_whitespace).unwrap_or(args.len());
+    let token = &args[..token_len];
+    if let Some(index) = token.find(".wgsl") {
+        let len = index + ".wgsl".len();
+        return Some((&token[..len], len));
+    }
+
+    let mut len = ident_len(args);
+    if len == 0 {
+        return None;
+    }
+    while let Some(rest) = args[len..].strip_prefix("::") {
+        let next = ident_len(rest);
+        if next == 0 {
+            break;
+        }
+        len += "::".len() + next;
+    }
+    Some((&args[..len], len))
+}
+
+/// Finds a `with (...)` clause at the end of an import, between `start` and `end`, giving the text within the
+/// parentheses and the span of the whole clause.
+fn import_params(source: &str, start: usize, end: usize) -> Option<(&str, Range<usize>)> {
+    let text = &source[start..end];
+    let close = text.trim_end().strip_suffix(')')?.len();
+    let open = text[..close].rfind('(')?;
+    let before = text[..open].trim_end().strip_suffix("with")?;
+    if !before.ends_with(char::is_whitespace) && !before.is_empty() {
+        return None;
+    }
+
+    let clause_start = start + before.len();
+    Some((&text[open + 1..close], clause_start..start + close + 1))
+}
+
+/// Parses the preprocessor directive starting with the `#` at `start`, recording any comments within it in `comments`.
+fn parse_directive<'a>(
+    source: &'a str,
+    start: usize,
+    comments: &mut Vec<Range<usize>>,
+) -> (Option<Directive<'a>>, usize) {
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |len| start + len);
+    let after_hash = source[start + 1..line_end].trim_start();
+    let name_len = ident_len(after_hash);
+    let name = &after_hash[..name_len];
+    let args_start = line_end - after_hash[name_len..].trim_start().len();
+
+    // Paths may contain `/*` in globs, so the path is read before looking for comments
+    if name == "import" || name == "export" {
+        let Some((path, path_len)) = import_path(&source[args_start..line_end]) else {
+            return (None, directive_end(source, start, comments));
+        };
+        let path_end = args_start + path_len;
+        let end = directive_end(source, path_end, comments).max(path_end);
+        let (tail_end, params, params_span) = match import_params(source, path_end, end) {
+            Some((params, params_span)) => (params_span.start, Some(params), Some(params_span)),
+            None => (end, None, None),
+        };
+        let directive = Directive::Import {
+            span: start..end,
+            path,
+            path_span: args_start..path_end,
+            tail: source[path_end..tail_end].trim(),
+            params,
+            params_span,
+            reexport: name == "export",
+        };
+        return (Some(directive), end);
+    }
+
+    let end = directive_end(source, start, comments);
+    let args = source[args_start.min(end)..end].trim();
+    let directive = match name {
+        "define_import_path" => {
+            let name_len = args.find(char::is_whitespace).unwrap_or(args.len());
+            Some(Directive::DefineImportPath {
+                span: start..end,
+                name: &args[..name_len],
+            })
+        }
+        "" => None,
+        _ => Some(Directive::Other {
+            span: start..end,
+            name,
+            args,
+        }),
+    };
+    (directive, end)
+}
+
+/// Parses an `@export struct Name` starting with the `@` at `start`.
+fn parse_export(source: &str, start: usize) -> Option<Directive<'_>> {
+    let rest = source[start + 1..].trim_start();
+    let rest = rest.strip_prefix("export")?;
+    let span_end = source.len() - rest.len();
+    if rest.starts_with(is_ident_char) {
+        return None;
+    }
+
+    let rest = rest.trim_start().strip_prefix("struct")?;
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let rest = rest.trim_start();
+    let struct_name = &rest[..ident_len(rest)];
+    if struct_name.is_empty() {
+        return None;
+    }
+
+    Some(Directive::ExportStruct {
+        span: start..span_end,
+        struct_name,
+    })
+}
+
+/// Finds every preprocessor directive and `@export` attribute in a shader's source, in order, along with the range of
+/// every comment.
+fn scan(source: &str) -> (Vec<Directive<'_>>, Vec<Range<usize>>) {
+    let mut directives = Vec::new();
+    let mut comments = Vec::new();
+
+    let mut line_start = true;
+    let mut i = 0;
+    while i < source.len() {
+        let rest = &source[i..];
+        if let Some(len) = comment_len(rest) {
+            comments.push(i..i + len);
+            i += len;
+            continue;
+        }
+
+        let c = rest.chars().next().expect("not at end");
+        if c == '\n' {
+            line_start = true;
+        } else if c == '#' && line_start {
+            let (directive, end) = parse_directive(source, i, &mut comments);
+            directives.extend(directive);
+            line_start = false;
+            i = end;
+            continue;
+        } else if c == '@' {
+            directives.extend(parse_export(source, i));
+            line_start = false;
+        } else if !c.is_whitespace() {
+            line_start = false;
+        }
+        i += c.len_utf8();
+    }
+
+    (directives, comments)
+}
+
+/// Finds every preprocessor directive and `@export` attribute in a shader's source, in order. Directives must be the
+/// first thing on their line, and anything within a comment is ignored.
+pub(crate) fn directives(source: &str) -> Vec<Directive<'_>> {
+    scan(source).0
+}
+
+/// Replaces every comment in a shader's source with spaces, so that it can be searched without finding anything that
+/// has been commented out. Newlines are kept, and everything else stays at the same offset.
+pub(crate) fn strip_comments(source: &str) -> String {
+    let mut stripped = String::with_capacity(source.len());
+    let mut last = 0;
+    for comment in scan(source).1 {
+        stripped.push_str(&source[last..comment.start]);
+        for c in source[comment.clone()].chars() {
+            match c {
+                '\n' => stripped.push('\n'),
+                c => stripped.push_str(&" ".repeat(c.len_utf8())),
+            }
+        }
+        last = comment.end;
+    }
+    stripped.push_str(&source[last..]);
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_nested_block_comments() {
+        let source = "/* outer /* inner */\n#import hidden.wgsl\n*/\n#import shown.wgsl\n";
+        let directives = directives(source);
+        assert_eq!(directives.len(), 1);
+        assert!(matches!(
+            directives[0],
+            Directive::Import {
+                path: "shown.wgsl",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn keeps_comment_markers_inside_strings() {
+        let source = "#include \"http://example.com/a.wgsl\" // comment\n";
+        let directives = directives(source);
+        assert_eq!(
+            directives,
+            [Directive::Other {
+                span: 0..36,
+                name: "include",
+                args: "\"http://example.com/a.wgsl\"",
+            }]
+        );
+    }
+
+    #[test]
+    fn continues_imports_over_braces() {
+        let source =
+            "#import utils.wgsl::{\n    first, // the first\n    second,\n}\nfn main() {}\n";
+        let directives = directives(source);
+        assert_eq!(
+            directives,
+            [Directive::Import {
+                span: 0..59,
+                path: "utils.wgsl",
+                path_span: 8..18,
+                tail: "::{\n    first, // the first\n    second,\n}",
+                params: None,
+                params_span: None,
+                reexport: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn reads_import_params() {
+        let source = "#import blur.wgsl as Blur with (RADIUS = 5)\n";
+        let directives = directives(source);
+        assert_eq!(
+            directives,
+            [Directive::Import {
+                span: 0..43,
+                path: "blur.wgsl",
+                path_span: 8..17,
+                tail: "as Blur",
+                params: Some("RADIUS = 5"),
+                params_span: Some(26..43),
+                reexport: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn reads_globs_as_paths() {
+        let source = "#import lights/*.wgsl\nfn main() {}\n";
+        let directives = directives(source);
+        assert!(matches!(
+            directives.as_slice(),
+            [Directive::Import {
+                path: "lights/*.wgsl",
+                path_span,
+                ..
+            }] if *path_span == (8..21)
+        ));
+        assert_eq!(strip_comments(source), source);
+    }
+
+    #[test]
+    fn reads_quoted_paths() {
+        let source = "#import \"my shaders/utils.wgsl\"::{light}\n";
+        let directives = directives(source);
+        assert_eq!(
+            directives,
+            [Directive::Import {
+                span: 0..40,
+                path: "my shaders/utils.wgsl",
+                path_span: 8..31,
+                tail: "::{light}",
+                params: None,
+                params_span: None,
+                reexport: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn only_reads_directives_at_line_starts() {
+        let source = "fn main() {} #ifdef A\n  #ifdef B\n@export struct Light {}\n";
+        let directives = directives(source);
+        assert_eq!(
+            directives,
+            [
+                Directive::Other {
+                    span: 24..32,
+                    name: "ifdef",
+                    args: "B",
+                },
+                Directive::ExportStruct {
+                    span: 33..40,
+                    struct_name: "Light",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn strips_comments_in_place() {
+        let source = "fn a() {} // fn b() {}\n/* fn c() {}\n*/ #ifdef D // E\n";
+        let stripped = strip_comments(source);
+        assert_eq!(stripped.len(), source.len());
+        assert_eq!(
+            stripped,
+            "fn a() {}             \n            \n   #ifdef D     \n"
+        );
+    }
+}
@@ -1,57 +1,86 @@
 #![doc = include_str!("../README.md")]
+#![cfg_attr(nightly_tracking, feature(track_path, proc_macro_tracked_env))]
 
+mod args;
+mod cfg_defs;
+mod conditionals;
+mod config;
+mod embed;
 mod error;
 mod exports;
 mod files;
 mod imports;
+mod includes;
+mod invocation;
+mod lexer;
 mod module;
+mod packages;
+mod prelude;
 mod result;
 mod source;
+mod source_map;
+mod suggestions;
+mod variants;
 
-use std::{fs::File, io::Read, path::PathBuf};
+use std::collections::HashMap;
 
-use files::AbsoluteRustFilePathBuf;
+use args::MacroArgs;
+use config::Config;
+use files::{AbsoluteRustFilePathBuf, AbsoluteWGSLFilePathBuf};
+use naga_oil::compose::ShaderDefValue;
 use quote::ToTokens;
 use source::Sourcecode;
 use syn::token::Brace;
+use variants::Variant;
 
-// Hacky polyfill for `proc_macro::Span::source_file`
-fn find_me(root: &str, pattern: &str) -> Option<PathBuf> {
-    let mut options = Vec::new();
-
-    for path in glob::glob(&std::path::Path::new(root).join("**/*.rs").to_string_lossy())
-        .unwrap()
-        .flatten()
-    {
-        if let Ok(mut f) = File::open(&path) {
-            let mut contents = String::new();
-            f.read_to_string(&mut contents).ok();
-            if contents.contains(pattern) {
-                options.push(path.to_owned());
-            }
+/// The items generated by composing a shader with one set of definitions.
+struct ShaderItems {
+    items: Vec<syn::Item>,
+    /// Every file that was read to generate the items.
+    files: Vec<AbsoluteWGSLFilePathBuf>,
+    /// Every error found, which the items don't include.
+    errors: Vec<String>,
+}
+
+/// Composes a shader with a set of definitions, giving the items to inject into the user's module, every file that was
+/// read to generate them and every error found.
+fn shader_items(
+    invocation_path: &AbsoluteRustFilePathBuf,
+    requested_path: &syn::LitStr,
+    shader_defs: HashMap<String, ShaderDefValue>,
+    config: &Config,
+) -> ShaderItems {
+    let sourcecode = Sourcecode::new(
+        invocation_path.clone(),
+        requested_path.value(),
+        shader_defs,
+        config.clone(),
+    );
+    let sourcecode = match sourcecode {
+        Ok(sourcecode) => sourcecode,
+        Err(message) => {
+            return ShaderItems {
+                items: Vec::new(),
+                files: Vec::new(),
+                errors: vec![message],
+            };
         }
-    }
+    };
 
-    match options.as_slice() {
-        [] => None,
-        [v] => Some(v.clone()),
-        _ => panic!(
-            "found more than one contender for macro invocation location. \
-            This won't be an issue once `proc_macro_span` is stabalized, \
-            but until then each instance of the `include_wgsl_oil` \
-            must be present in the source text, and each must have a unique argument. \
-            found locations: {:?}",
-            options
-                .into_iter()
-                .map(|path| format!("`{}`", path.display()))
-                .collect::<Vec<String>>()
-        ),
+    let mut result = sourcecode.complete();
+
+    result.validate();
+
+    ShaderItems {
+        items: result.items(),
+        files: result.files(),
+        errors: result.errors(),
     }
 }
 
 #[proc_macro_attribute]
 pub fn include_wgsl_oil(
-    path: proc_macro::TokenStream,
+    args: proc_macro::TokenStream,
     module: proc_macro::TokenStream,
 ) -> proc_macro::TokenStream {
     // Parse module definitions and error if it contains anything
@@ -71,26 +100,85 @@ pub fn include_wgsl_oil(
     }
     module.semi = None;
 
-    let requested_path = syn::parse_macro_input!(path as syn::LitStr);
-    let requested_path = requested_path.value();
-
-    let root = std::env::var("CARGO_MANIFEST_DIR").expect("proc macros should be run using cargo");
-    let invocation_path = match find_me(&root, &format!("\"{}\"", requested_path)) {
-        Some(invocation_path) => AbsoluteRustFilePathBuf::new(invocation_path),
-        None => {
-            panic!(
-                "could not find invocation point - maybe it was in a macro? This won't be an issue once \
-                `proc_macro_span` is stabalized, but until then each instance of the `include_wgsl_oil` \
-                must be present in the source text, and each must have a unique argument."
-            )
+    let args = syn::parse_macro_input!(args as MacroArgs);
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(errors) => {
+            return errors
+                .into_iter()
+                .map(|message| syn::Error::new(args.path.span(), message).to_compile_error())
+                .collect::<proc_macro2::TokenStream>()
+                .into()
         }
     };
+    let config = match args.apply_to(&config) {
+        Ok(config) => config,
+        Err(err) => return err.to_compile_error().into(),
+    };
 
-    let sourcecode = Sourcecode::new(invocation_path, requested_path);
+    let invocation_path =
+        invocation::find_invocation_file(&args.path).and_then(AbsoluteRustFilePathBuf::new);
+    let invocation_path = match invocation_path {
+        Ok(invocation_path) => invocation_path,
+        Err(message) => {
+            return syn::Error::new(args.path.span(), message)
+                .to_compile_error()
+                .into()
+        }
+    };
 
-    let mut result = sourcecode.complete();
+    let mut items = cfg_defs::cfg_gated_items(&args.cfg_defs(), |cfg_defs| {
+        let mut shader_defs = config.defs.clone();
+        shader_defs.extend(cfg_defs);
 
-    result.validate();
+        let compose = |variant: &Variant| {
+            let mut shader_defs = shader_defs.clone();
+            shader_defs.extend(variant.shader_defs());
+            shader_items(&invocation_path, &args.path, shader_defs, &config)
+        };
+        // Errors, on the path given to the macro
+        let error_items = |errors: &[String]| {
+            errors
+                .iter()
+                .map(|message| error::compile_error_item(message, args.path.span()))
+                .collect::<Vec<_>>()
+        };
+        if args.variants.is_empty() {
+            let mut shader = compose(&Variant::default());
+            let mut items = error_items(&shader.errors);
+            items.append(&mut shader.items);
+            return (items, shader.files);
+        }
+
+        // Every variant is composed, so that errors which only some variants have are all reported at once
+        let mut files = Vec::new();
+        let mut variants = Vec::new();
+        let mut errors = Vec::new();
+        for variant in Variant::combinations(&args.variants) {
+            let mut shader = compose(&variant);
+            files.append(&mut shader.files);
+            errors.push(shader.errors);
+            variants.push((variant, shader.items));
+        }
+        let errors = variants::variant_errors(
+            variants
+                .iter()
+                .map(|(variant, _)| variant)
+                .zip(errors)
+                .collect(),
+        );
+        let mut items = error_items(&errors);
+        items.append(&mut variants::variant_items(variants));
+        (items, files)
+    });
+
+    // Re-run macro on configuration change
+    for file in &config.files {
+        let file = file.to_string_lossy();
+        items.push(syn::parse_quote! {
+            const _: &[u8] = include_bytes!(#file);
+        });
+    }
 
     // Inject items
     module
@@ -98,7 +186,7 @@ pub fn include_wgsl_oil(
         .as_mut()
         .expect("set to some at start")
         .1
-        .append(&mut result.items());
+        .append(&mut items);
 
     module.to_token_stream().into()
 }
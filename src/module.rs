@@ -1,13 +1,24 @@
-use std::{borrow::Cow, collections::HashMap, fmt::Display, path::PathBuf};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Display,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
 
 use naga_oil::compose::{
     ComposableModuleDescriptor, NagaModuleDescriptor, ShaderDefValue, ShaderLanguage,
 };
 
 use crate::{
-    exports,
-    files::{AbsoluteRustRootPathBuf, AbsoluteWGSLFilePathBuf},
-    imports,
+    conditionals, embed, exports,
+    files::{self, AbsoluteWGSLFilePathBuf, SearchPaths},
+    imports::{self, ImportResolutionError, ReExports},
+    includes::SplicedSource,
+    lexer::{self, Directive},
+    packages,
+    prelude::Prelude,
+    suggestions,
 };
 
 pub(crate) struct OwnedComposableModuleDescriptor {
@@ -15,6 +26,8 @@ pub(crate) struct OwnedComposableModuleDescriptor {
     file_path: String,
     as_name: String,
     shader_defs: HashMap<String, ShaderDefValue>,
+    /// The files embedded into the source with `#embed`.
+    data_files: Vec<PathBuf>,
 }
 
 impl OwnedComposableModuleDescriptor {
@@ -28,12 +41,18 @@ impl OwnedComposableModuleDescriptor {
             shader_defs: self.shader_defs.clone(),
         }
     }
+
+    pub(crate) fn data_files(&self) -> &[PathBuf] {
+        &self.data_files
+    }
 }
 
 pub(crate) struct OwnedNagaModuleDescriptor {
     source: String,
     file_path: String,
     shader_defs: HashMap<String, ShaderDefValue>,
+    /// The files embedded into the source with `#embed`.
+    data_files: Vec<PathBuf>,
 }
 
 impl OwnedNagaModuleDescriptor {
@@ -46,26 +65,294 @@ impl OwnedNagaModuleDescriptor {
             shader_type: naga_oil::compose::ShaderType::Wgsl,
         }
     }
+
+    pub(crate) fn data_files(&self) -> &[PathBuf] {
+        &self.data_files
+    }
 }
 
-/// A single requested import to a shader.
-#[derive(Debug, Hash, PartialEq, Eq, Clone)]
+/// A single requested import to a shader. A file imported with parameters, e.g. `#import blur.wgsl with (RADIUS = 5)`,
+/// is a different module for each set of parameters, as are the files that it imports.
+#[derive(Debug, PartialEq, Clone)]
 pub(crate) struct Module {
     path: AbsoluteWGSLFilePathBuf,
+    /// The shader defs that the module is instantiated with, sorted by name.
+    params: Vec<(String, ShaderDefValue)>,
+}
+
+impl Eq for Module {}
+
+impl Hash for Module {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.path.hash(state);
+        for (name, value) in &self.params {
+            name.hash(state);
+            conditionals::display_value(value).hash(state);
+        }
+    }
 }
 
 impl Module {
     /// Given a path to a file and the string given to describe an import, tries to resolve the requested import file.
     pub(crate) fn from_path(path: AbsoluteWGSLFilePathBuf) -> Self {
-        Self { path }
+        Self {
+            path,
+            params: Vec::new(),
+        }
+    }
+
+    /// Gives this module as imported by `importing` with the given parameters. Parameters are inherited from the
+    /// importing module, and parameters given to the import override those inherited.
+    pub(crate) fn instantiate(
+        self,
+        importing: &Module,
+        params: &[(String, ShaderDefValue)],
+    ) -> Self {
+        let mut all_params = importing.params.clone();
+        for (name, value) in params {
+            all_params.retain(|(other, _)| other != name);
+            all_params.push((name.clone(), *value));
+        }
+        all_params.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        Self {
+            path: self.path,
+            params: all_params,
+        }
+    }
+
+    /// The shader defs that the module is instantiated with.
+    pub(crate) fn params(&self) -> &[(String, ShaderDefValue)] {
+        &self.params
+    }
+
+    /// Expands environment variables such as `$OUT_DIR` in a requested import.
+    fn expand_request(
+        importing: &Module,
+        request_string: &str,
+    ) -> Result<String, ImportResolutionError> {
+        files::expand_env_vars(request_string).map_err(|variable| {
+            ImportResolutionError::UnsetVariable {
+                requested: request_string.to_owned(),
+                importer: importing.clone(),
+                variable,
+            }
+        })
+    }
+
+    /// Gives the canonical path of a file found while resolving a request.
+    fn canonical(
+        importing: &Module,
+        request_string: &str,
+        path: &Path,
+    ) -> Result<PathBuf, ImportResolutionError> {
+        path.canonicalize()
+            .map_err(|e| ImportResolutionError::Invalid {
+                requested: request_string.to_owned(),
+                importer: importing.clone(),
+                message: format!("could not read `{}`: {}", path.display(), e),
+            })
+    }
+
+    /// Gives the module for a shader file found while resolving an import.
+    fn found(
+        importing: &Module,
+        request_string: &str,
+        path: &Path,
+    ) -> Result<Self, ImportResolutionError> {
+        let path = Self::canonical(importing, request_string, path)?;
+        AbsoluteWGSLFilePathBuf::new(path)
+            .map(Self::from_path)
+            .map_err(|message| ImportResolutionError::Invalid {
+                requested: request_string.to_owned(),
+                importer: importing.clone(),
+                message,
+            })
+    }
+
+    /// Finds the folder that a dependency exports its shaders from, for an import of the form `@package/...`.
+    fn package_shader_dir(
+        importing: &Module,
+        request_string: &str,
+        package: &str,
+    ) -> Result<PathBuf, ImportResolutionError> {
+        packages::dependency_shader_dir(package).map_err(|message| ImportResolutionError::Package {
+            requested: request_string.to_owned(),
+            importer: importing.clone(),
+            message,
+        })
+    }
+
+    /// Given a path to a file and the string given to describe an import, tries to resolve every requested import file.
+    /// Globs such as `materials/**/*.wgsl` give every matching file in sorted order, from the first of the folders
+    /// searched that contains any matching files. Other imports give the single file found by [`Module::resolve_module`].
+    pub(crate) fn resolve_modules(
+        importing: &Module,
+        search_paths: &SearchPaths,
+        request_string: &str,
+    ) -> Result<Vec<Self>, ImportResolutionError> {
+        if !files::is_glob(request_string) {
+            return Self::resolve_module(importing, search_paths, request_string)
+                .map(|module| vec![module]);
+        }
+
+        let expanded = Self::expand_request(importing, request_string)?;
+        let (folders, pattern) = match packages::split_package_import(&expanded) {
+            Some((package, pattern)) => (
+                vec![Self::package_shader_dir(importing, &expanded, package)?],
+                pattern.to_owned(),
+            ),
+            None => match files::split_absolute_glob(&expanded) {
+                Some((folder, pattern)) => (vec![folder], pattern),
+                None => (
+                    Self::search_folders(importing, search_paths),
+                    expanded.clone(),
+                ),
+            },
+        };
+
+        let mut tried_paths = Vec::new();
+        for folder in folders {
+            let relative = folder.join(&pattern);
+            if tried_paths.contains(&relative) {
+                continue;
+            }
+            tried_paths.push(relative);
+
+            let matched = files::glob_files(&folder, &pattern);
+            if !matched.is_empty() {
+                return matched
+                    .into_iter()
+                    .map(|path| Self::found(importing, request_string, &path))
+                    .collect();
+            }
+        }
+
+        Err(ImportResolutionError::Unresolved {
+            requested: request_string.to_owned(),
+            importer: importing.clone(),
+            searched: tried_paths,
+            suggestions: Vec::new(),
+        })
+    }
+
+    /// The folders that a relative path requested by a module is looked for in, in order: the module's own folder, then
+    /// the source root, then each include directory.
+    fn search_folders(importing: &Module, search_paths: &SearchPaths) -> Vec<PathBuf> {
+        let parent = importing
+            .path
+            .parent()
+            .expect("every absolute path to a file has a parent");
+        std::iter::once(parent)
+            .chain(search_paths.folders())
+            .map(|folder| folder.to_path_buf())
+            .collect()
+    }
+
+    /// Rewrites the path or logical name given to an import so that it finds the same files when imported from any
+    /// module, by giving the absolute path of the file or glob that it resolves to. Logical names and imports from
+    /// packages find the same files from every module already, so are only expanded.
+    pub(crate) fn absolute_request(
+        importing: &Module,
+        search_paths: &SearchPaths,
+        request_string: &str,
+    ) -> Result<String, ImportResolutionError> {
+        let expanded = Self::expand_request(importing, request_string)?;
+        let is_logical_name = !files::is_glob(&expanded) && !expanded.ends_with(".wgsl");
+        if is_logical_name
+            || packages::split_package_import(&expanded).is_some()
+            || Path::new(&expanded).is_absolute()
+        {
+            return Ok(expanded);
+        }
+
+        if !files::is_glob(&expanded) {
+            let path = Self::resolve_file(importing, search_paths, request_string)?;
+            return Ok(path.to_string_lossy().into_owned());
+        }
+        // Globs which match nothing are left to be reported when they are resolved
+        let matched = Self::search_folders(importing, search_paths)
+            .into_iter()
+            .find(|folder| !files::glob_files(folder, &expanded).is_empty());
+        Ok(match matched {
+            Some(folder) => folder.join(&expanded).to_string_lossy().into_owned(),
+            None => expanded,
+        })
     }
 
     /// Given a path to a file and the string given to describe an import, tries to resolve the requested import file.
+    /// Imports of the form `@package/path/to/file.wgsl` are resolved against the folder that a dependency exports its
+    /// shaders from, and environment variables such as `$OUT_DIR` are expanded before resolving.
     pub(crate) fn resolve_module(
         importing: &Module,
-        source_root: Option<&AbsoluteRustRootPathBuf>,
+        search_paths: &SearchPaths,
+        request_string: &str,
+    ) -> Result<Self, ImportResolutionError> {
+        let expanded = Self::expand_request(importing, request_string)?;
+
+        // Logical names given by `#define_import_path`, which may be followed by the name of an imported item
+        if !expanded.ends_with(".wgsl") && packages::split_package_import(&expanded).is_none() {
+            let mut name = expanded.as_str();
+            loop {
+                if let Some(path) = search_paths.logical_module(name) {
+                    return Self::found(importing, request_string, path);
+                }
+                match name.rsplit_once("::") {
+                    Some((prefix, _)) => name = prefix,
+                    None => {
+                        let logical_names = search_paths.logical_names().map(str::to_owned);
+                        return Err(ImportResolutionError::Unresolved {
+                            requested: request_string.to_owned(),
+                            importer: importing.clone(),
+                            searched: Vec::new(),
+                            suggestions: suggestions::closest(&expanded, logical_names),
+                        });
+                    }
+                }
+            }
+        }
+
+        let path = Self::resolve_file(importing, search_paths, request_string)?;
+        Self::found(importing, request_string, &path)
+    }
+
+    /// Given a path to a file requested by a module, such as an import or embedded data, tries to find the file, giving
+    /// its canonical path. Environment variables are expanded, then paths of the form `@package/path/to/file` are
+    /// resolved against the folder that a dependency exports its shaders from, and other paths relative to the
+    /// requesting file, then the source root, then each include directory.
+    pub(crate) fn resolve_file(
+        importing: &Module,
+        search_paths: &SearchPaths,
         request_string: &str,
-    ) -> Result<Self, Vec<PathBuf>> {
+    ) -> Result<PathBuf, ImportResolutionError> {
+        let unresolved = |searched, suggestions| ImportResolutionError::Unresolved {
+            requested: request_string.to_owned(),
+            importer: importing.clone(),
+            searched,
+            suggestions,
+        };
+
+        let expanded = Self::expand_request(importing, request_string)?;
+        let request_string = expanded.as_str();
+
+        if let Some((package, path)) = packages::split_package_import(request_string) {
+            let shader_dir = Self::package_shader_dir(importing, request_string, package)?;
+            let relative = shader_dir.join(path);
+            if relative.is_file() {
+                return Self::canonical(importing, request_string, &relative);
+            }
+            // Only requests for shader files are given suggestions
+            let mut files = Vec::new();
+            if path.ends_with(".wgsl") {
+                files::collect_wgsl_files(&shader_dir, &mut files);
+            }
+            let suggestions = Self::close_files(path, &files, &[shader_dir.as_path()], None)
+                .into_iter()
+                .map(|path| format!("@{}/{}", package, path))
+                .collect();
+            return Err(unresolved(vec![relative], suggestions));
+        }
+
         let mut tried_paths = Vec::new();
 
         // Try interpret as relative to importing file
@@ -76,48 +363,104 @@ impl Module {
         let relative = parent.join(request_string);
         tried_paths.push(relative.clone());
         if relative.is_file() {
-            let path = relative.canonicalize().unwrap();
-            return Ok(Self {
-                path: AbsoluteWGSLFilePathBuf::new(path),
-            });
+            return Self::canonical(importing, request_string, &relative);
         }
 
-        // Try interpret as relative to source root
-        if let Some(source_root) = source_root {
-            let relative = source_root.join(request_string);
+        // Try interpret as relative to source root, then to each include directory
+        for folder in search_paths.folders() {
+            let relative = folder.join(request_string);
+            if tried_paths.contains(&relative) {
+                continue;
+            }
             tried_paths.push(relative.clone());
             if relative.is_file() {
-                let path = relative.canonicalize().unwrap();
-                return Ok(Self {
-                    path: AbsoluteWGSLFilePathBuf::new(path),
-                });
+                return Self::canonical(importing, request_string, &relative);
             }
         }
 
-        Err(tried_paths)
+        // Only requests for shader files are given suggestions. The files in the search folders are already indexed, so
+        // only the requesting file's folder may need walking
+        let folders = search_paths.folders().collect::<Vec<_>>();
+        let mut files = Vec::new();
+        if request_string.ends_with(".wgsl") {
+            files.extend_from_slice(search_paths.files());
+            if !folders.iter().any(|folder| parent.starts_with(folder)) {
+                files::collect_wgsl_files(parent, &mut files);
+            }
+        }
+        let suggestions = Self::close_files(request_string, &files, &folders, Some(parent));
+        Err(unresolved(tried_paths, suggestions))
+    }
+
+    /// Finds the files closest to a requested path which couldn't be found, out of the given files, as they could be
+    /// requested relative to one of the given folders, or to the requesting file's folder.
+    fn close_files(
+        request_string: &str,
+        files: &[PathBuf],
+        folders: &[&Path],
+        parent: Option<&Path>,
+    ) -> Vec<String> {
+        let as_request = |path: &Path| path.to_string_lossy().replace('\\', "/");
+        let mut candidates = Vec::new();
+        for file in files {
+            for folder in folders {
+                if let Ok(relative) = file.strip_prefix(folder) {
+                    candidates.push(as_request(relative));
+                }
+            }
+            // Paths relative to the requesting file may need to leave its folder, e.g. `../shared/utils.wgsl`
+            if let Some(relative) = parent.and_then(|parent| pathdiff::diff_paths(file, parent)) {
+                candidates.push(as_request(&relative));
+            }
+        }
+        suggestions::closest(request_string, candidates)
     }
 
     pub(crate) fn to_composable_module_descriptor(
         &self,
         module_names: &HashMap<Module, String>,
-        source_root: Option<&AbsoluteRustRootPathBuf>,
+        re_exports: &ReExports,
+        prelude: &Prelude,
+        search_paths: &SearchPaths,
         definitions: HashMap<String, ShaderDefValue>,
     ) -> Result<OwnedComposableModuleDescriptor, Vec<String>> {
-        let source = self.read_to_string();
+        let spliced = self.read_spliced(&definitions).map_err(|e| vec![e])?;
+        let source = prelude.apply(self, spliced).source;
 
-        if source.contains("#define") {
-            return Err(vec![format!(
-                "imported shader file `{}` contained a `#define` statement \
-                - only top-level files may contain preprocessor definitions",
-                self.path.display()
-            )]);
+        // `naga_oil` only allows `#define` in top-level files, so the definitions are given with the module's shader defs
+        // instead, and the directives are replaced with equivalent whitespace
+        let define_spans = lexer::directives(&source)
+            .into_iter()
+            .filter_map(|directive| match directive {
+                Directive::Other {
+                    name: "define",
+                    span,
+                    ..
+                } => Some(span),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut source = source;
+        for span in define_spans {
+            source.replace_range(span.clone(), &" ".repeat(span.len()));
         }
 
         // Replace `@export` directives with equivalent whitespace
         let (source, _) = exports::strip_exports(&source);
 
+        // Replace `#embed` directives with the data they embed
+        let (source, data_files) =
+            embed::replace_embeds(&source, self, search_paths, &definitions)?;
+
         // Replace `#import` names with substitutions
-        let source = imports::replace_imports_in_source(&source, self, source_root, module_names);
+        let source = imports::replace_imports_in_source(
+            &source,
+            self,
+            search_paths,
+            module_names,
+            re_exports,
+            &definitions,
+        )?;
 
         let name = &module_names[self];
         Ok(OwnedComposableModuleDescriptor {
@@ -125,27 +468,43 @@ impl Module {
             file_path: self.path.to_string_lossy().to_string(),
             as_name: name.clone(),
             shader_defs: definitions,
+            data_files,
         })
     }
 
     pub(crate) fn to_naga_module_descriptor(
         &self,
         module_names: &HashMap<Module, String>,
-        source_root: Option<&AbsoluteRustRootPathBuf>,
+        re_exports: &ReExports,
+        prelude: &Prelude,
+        search_paths: &SearchPaths,
         definitions: HashMap<String, ShaderDefValue>,
     ) -> Result<OwnedNagaModuleDescriptor, Vec<String>> {
-        let source = self.read_to_string();
+        let spliced = self.read_spliced(&definitions).map_err(|e| vec![e])?;
+        let source = prelude.apply(self, spliced).source;
 
         // Replace `@export` directives with equivalent whitespace
         let (source, _) = exports::strip_exports(&source);
 
+        // Replace `#embed` directives with the data they embed
+        let (source, data_files) =
+            embed::replace_embeds(&source, self, search_paths, &definitions)?;
+
         // Replace `#import` names with substitutions
-        let source = imports::replace_imports_in_source(&source, self, source_root, module_names);
+        let source = imports::replace_imports_in_source(
+            &source,
+            self,
+            search_paths,
+            module_names,
+            re_exports,
+            &definitions,
+        )?;
 
         Ok(OwnedNagaModuleDescriptor {
             source,
             file_path: self.path.to_string_lossy().to_string(),
             shader_defs: definitions,
+            data_files,
         })
     }
 
@@ -153,20 +512,48 @@ impl Module {
         self.path.clone()
     }
 
-    pub(crate) fn read_to_string(&self) -> String {
-        std::fs::read_to_string(&*self.path).unwrap_or_else(|_| {
-            panic!(
-                "file `{}` exists but could not be read",
-                self.path.display()
-            )
-        })
+    /// Reads the file, with the files that it includes with the given shader defs spliced in.
+    pub(crate) fn read_to_string(
+        &self,
+        shader_defs: &HashMap<String, ShaderDefValue>,
+    ) -> Result<String, String> {
+        self.read_spliced(shader_defs).map(|spliced| spliced.source)
+    }
+
+    /// Reads the file, with the files that it includes with the given shader defs spliced in, along with where each line
+    /// came from.
+    pub(crate) fn read_spliced(
+        &self,
+        shader_defs: &HashMap<String, ShaderDefValue>,
+    ) -> Result<SplicedSource, String> {
+        SplicedSource::read(&self.path, shader_defs)
     }
 
-    /// Gets the name of the file, without the `.wgsl` extension.
+    /// Gets the logical name that the file declares with `#define_import_path`, if any.
+    /// Modules instantiated with parameters are given their own names instead.
+    pub(crate) fn logical_name(&self) -> Option<String> {
+        if !self.params.is_empty() {
+            return None;
+        }
+        // A file's logical name can't depend on the shader defs it is composed with
+        imports::defined_import_path(&self.read_to_string(&HashMap::new()).ok()?).map(str::to_owned)
+    }
+
+    /// Gets the name of the file, without the `.wgsl` extension, followed by any parameters,
+    /// e.g. `blur_radius_5`.
     pub(crate) fn file_name(&self) -> String {
         let name = self.path.file_name().unwrap().to_string_lossy();
         assert!(name.ends_with(".wgsl"));
-        name[..(name.len() - 5)].to_owned()
+        let mut name = name[..(name.len() - 5)].to_owned();
+        for (param, value) in &self.params {
+            name = format!(
+                "{}_{}_{}",
+                name,
+                param.to_lowercase(),
+                conditionals::ident_value(value)
+            );
+        }
+        name
     }
 
     pub(crate) fn nth_path_component(&self, i: usize) -> Option<Cow<'_, str>> {
@@ -183,6 +570,110 @@ impl Module {
 
 impl Display for Module {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.path.display())
+        write!(f, "{}", self.path.display())?;
+        if !self.params.is_empty() {
+            let params = self
+                .params
+                .iter()
+                .map(|(name, value)| format!("{} = {}", name, conditionals::display_value(value)))
+                .collect::<Vec<_>>();
+            write!(f, " with ({})", params.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::test_shaders;
+
+    #[test]
+    fn suggests_files_in_search_folders() {
+        let folder = test_shaders::folder().join("suggestions");
+        let main = Module::from_path(test_shaders::shader("suggestions/nested/main.wgsl"));
+        let search_paths = SearchPaths::new(None, vec![folder.clone()]);
+
+        let Err(error) = Module::resolve_file(&main, &search_paths, "Utils.wgsl") else {
+            panic!("`Utils.wgsl` is resolved");
+        };
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "could not resolve import `Utils.wgsl` in file `{}`:\nlooked in location(s) `{}`, `{}`\n\
+                help: did you mean one of `utils.wgsl`, `../utils.wgsl`?",
+                main,
+                folder.join("nested").join("Utils.wgsl").display(),
+                folder.join("Utils.wgsl").display(),
+            )
+        );
+    }
+
+    #[test]
+    fn expands_env_vars_in_requests() {
+        let main = Module::from_path(test_shaders::shader("suggestions/nested/main.wgsl"));
+        let search_paths = SearchPaths::new(None, Vec::new());
+
+        let path = Module::resolve_file(
+            &main,
+            &search_paths,
+            "$CARGO_MANIFEST_DIR/tests/shaders/suggestions/utils.wgsl",
+        );
+        assert_eq!(
+            path.ok(),
+            test_shaders::shader("suggestions/utils.wgsl")
+                .canonicalize()
+                .ok()
+        );
+
+        let Err(error) =
+            Module::resolve_file(&main, &search_paths, "$INCLUDE_WGSL_OIL_UNSET/utils.wgsl")
+        else {
+            panic!("the unset variable is expanded");
+        };
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "could not resolve import `$INCLUDE_WGSL_OIL_UNSET/utils.wgsl` in file `{}`: environment variable \
+                `INCLUDE_WGSL_OIL_UNSET` is not set",
+                main
+            )
+        );
+    }
+
+    #[test]
+    fn resolves_logical_names() {
+        let main = Module::from_path(test_shaders::shader("logical/main.wgsl"));
+        let search_paths = SearchPaths::new(None, vec![test_shaders::folder().join("logical")]);
+        let lighting = test_shaders::shader("logical/lib/lighting.wgsl");
+
+        let module = Module::resolve_module(&main, &search_paths, "my_lib::lighting::shade");
+        assert_eq!(
+            module.ok().map(|module| module.path().to_path_buf()),
+            lighting.canonicalize().ok()
+        );
+
+        let Err(error) = Module::resolve_module(&main, &search_paths, "my_lib::lightning") else {
+            panic!("`my_lib::lightning` is resolved");
+        };
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "could not resolve import `my_lib::lightning` in file `{}`:\nno file in the source folder or \
+                include directories declares it with `#define_import_path`\nhelp: did you mean `my_lib::lighting`?",
+                main
+            )
+        );
+    }
+
+    #[test]
+    fn only_suggests_shader_files() {
+        let main = Module::from_path(test_shaders::shader("suggestions/nested/main.wgsl"));
+        let search_paths = SearchPaths::new(None, vec![test_shaders::folder().join("suggestions")]);
+
+        let Err(error) = Module::resolve_file(&main, &search_paths, "utils.bin") else {
+            panic!("`utils.bin` is resolved");
+        };
+        assert!(!error.to_string().contains("help:"));
     }
 }
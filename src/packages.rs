@@ -0,0 +1,199 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use crate::{
+    config::{self, Config},
+    files,
+};
+
+/// The root folders of a crate's dependencies, by the name that the crate refers to each dependency by, along with the
+/// modification time of the crate's manifest when they were read.
+type CachedDependencyRoots = (Option<SystemTime>, Result<HashMap<String, PathBuf>, String>);
+
+lazy_static::lazy_static! {
+    /// The root folders of the dependencies of each crate that has imported from a dependency, by the crate's root folder.
+    static ref DEPENDENCY_ROOTS: Mutex<HashMap<PathBuf, CachedDependencyRoots>> = Mutex::new(HashMap::new());
+}
+
+/// Splits an import of the form `@package/path/to/file.wgsl` into the package name and the path within the package.
+pub(crate) fn split_package_import(request_string: &str) -> Option<(&str, &str)> {
+    request_string.strip_prefix('@')?.split_once('/')
+}
+
+/// Asks cargo for the root folder of each direct dependency of the crate with the given root folder.
+fn read_dependency_roots(root: &Path) -> Result<HashMap<String, PathBuf>, String> {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_owned());
+    let manifest_path = root.join("Cargo.toml");
+    // Dependencies have already been fetched by the time that the crate is compiled, so there's no need to hit the network
+    let output = Command::new(cargo)
+        .arg("metadata")
+        .arg("--format-version=1")
+        .arg("--offline")
+        .arg("--manifest-path")
+        .arg(&manifest_path)
+        .output()
+        .map_err(|e| format!("could not run `cargo metadata`: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "`cargo metadata` failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let metadata: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("could not parse the output of `cargo metadata`: {}", e))?;
+
+    // The crate's folder may be reached through a symlink or `..`, so paths are compared once they have been resolved
+    let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let canonical_manifest_path = canonical(&manifest_path);
+    let packages = metadata["packages"].as_array().cloned().unwrap_or_default();
+    let package_root = |id: &serde_json::Value| {
+        packages
+            .iter()
+            .find(|package| package["id"] == *id)
+            .and_then(|package| package["manifest_path"].as_str())
+            .and_then(|manifest_path| Path::new(manifest_path).parent())
+            .map(Path::to_path_buf)
+    };
+
+    let this_package = packages
+        .iter()
+        .find(|package| {
+            package["manifest_path"]
+                .as_str()
+                .is_some_and(|path| canonical(Path::new(path)) == canonical_manifest_path)
+        })
+        .ok_or_else(|| {
+            format!(
+                "`cargo metadata` did not list the crate at `{}`",
+                root.display()
+            )
+        })?;
+    let node = metadata["resolve"]["nodes"]
+        .as_array()
+        .and_then(|nodes| nodes.iter().find(|node| node["id"] == this_package["id"]))
+        .ok_or_else(|| "`cargo metadata` did not resolve the crate's dependencies".to_owned())?;
+
+    // Dependencies are named as they would be in Rust source, so renamed dependencies use their new name
+    Ok(node["deps"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|dep| Some((dep["name"].as_str()?.to_owned(), package_root(&dep["pkg"])?)))
+        .collect())
+}
+
+/// Finds the folder that a dependency of the crate being compiled exports its shaders from.
+///
+/// If a `DEP_<PACKAGE>_SHADER_DIR` environment variable is given to the compiler, e.g. by a build script passing on the
+/// variable set by a dependency's `links` build script, it is used. Otherwise the dependency is found with `cargo metadata`,
+/// and its `shader_dir` is read from its configuration.
+pub(crate) fn dependency_shader_dir(package: &str) -> Result<PathBuf, String> {
+    dependency_shader_dir_for(&files::manifest_dir()?, package)
+}
+
+/// Finds the folder that a dependency of the crate with the given root folder exports its shaders from, in the same way
+/// as [`dependency_shader_dir`]. Each crate's dependencies are found once per process, and are only found again if its
+/// manifest changes.
+fn dependency_shader_dir_for(root: &Path, package: &str) -> Result<PathBuf, String> {
+    let package = package.replace('-', "_");
+
+    // A variable set by a build script already causes the crate to be rebuilt when it changes, but nightly compilers can
+    // track it themselves
+    let env_var = format!("DEP_{}_SHADER_DIR", package.to_uppercase());
+    #[cfg(nightly_tracking)]
+    let dir = proc_macro::tracked_env::var(&env_var);
+    #[cfg(not(nightly_tracking))]
+    let dir = std::env::var(&env_var);
+    if let Ok(dir) = dir {
+        let dir = PathBuf::from(dir);
+        if !dir.is_dir() {
+            return Err(format!(
+                "`{}` is set to `{}`, which is not a folder",
+                env_var,
+                dir.display()
+            ));
+        }
+        return Ok(dir);
+    }
+
+    let modified = config::modified_time(&root.join("Cargo.toml"));
+    let dependency_root = {
+        let mut cache = DEPENDENCY_ROOTS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (cached_modified, roots) = cache
+            .entry(root.to_path_buf())
+            .or_insert_with(|| (modified, read_dependency_roots(root)));
+        if *cached_modified != modified {
+            *cached_modified = modified;
+            *roots = read_dependency_roots(root);
+        }
+        match roots {
+            Ok(roots) => roots.get(&package).cloned(),
+            Err(e) => return Err(e.clone()),
+        }
+    };
+    let dependency_root = dependency_root
+        .ok_or_else(|| format!("`{}` is not a dependency of this crate", package))?;
+
+    let config = Config::load_for(dependency_root.clone()).map_err(|errors| {
+        format!(
+            "could not read the configuration of `{}`: {}",
+            package,
+            errors.join(", ")
+        )
+    })?;
+    config.shader_dir.clone().ok_or_else(|| {
+        format!(
+            "`{}` does not export any shaders - it should give a `shader_dir` in the \
+            `[package.metadata.include-wgsl-oil]` section of `{}`",
+            package,
+            dependency_root.join("Cargo.toml").display()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::test_shaders;
+
+    #[test]
+    fn finds_path_dependencies() {
+        let packages = test_shaders::folder().join("packages");
+        let shader_dir = dependency_shader_dir_for(&packages.join("app"), "shader-lib");
+        assert_eq!(shader_dir, Ok(packages.join("shader_lib").join("shaders")));
+    }
+
+    #[test]
+    fn finds_dependencies_of_crates_reached_through_parent_folders() {
+        let packages = test_shaders::folder().join("packages");
+        let root = packages.join("shader_lib").join("..").join("app");
+        let shader_dir = dependency_shader_dir_for(&root, "shader-lib")
+            .map(|shader_dir| shader_dir.canonicalize().unwrap());
+        assert_eq!(
+            shader_dir,
+            Ok(packages
+                .join("shader_lib")
+                .join("shaders")
+                .canonicalize()
+                .unwrap())
+        );
+    }
+
+    #[test]
+    fn reports_packages_which_are_not_dependencies() {
+        let packages = test_shaders::folder().join("packages");
+        let shader_dir = dependency_shader_dir_for(&packages.join("app"), "other-lib");
+        assert_eq!(
+            shader_dir,
+            Err("`other_lib` is not a dependency of this crate".to_owned())
+        );
+    }
+}
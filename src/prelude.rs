@@ -0,0 +1,239 @@
+use std::collections::HashSet;
+
+use crate::{
+    files::{AbsoluteWGSLFilePathBuf, SearchPaths},
+    imports::{self, ImportResolutionError},
+    includes::SplicedSource,
+    lexer::{self, Directive},
+    module::Module,
+};
+
+/// Checks that an import given in the configured prelude is a single import, written as it would be following `#import`.
+pub(crate) fn check_import(import: &str) -> Result<(), String> {
+    let directive = format!("#import {}", import.trim());
+    match lexer::directives(&directive).as_slice() {
+        [Directive::Import { span, .. }] if span.end == directive.len() => Ok(()),
+        _ => Err(format!(
+            "`{}` in `prelude` is not a valid import - write each import as it would follow `#import`, \
+            e.g. `\"utils.wgsl::{{my_fn}}\"`",
+            import
+        )),
+    }
+}
+
+/// The imports which are implicitly added to every shader, except to the files that the prelude imports, directly or
+/// indirectly, and to files which opt out with `#no_prelude`.
+#[derive(Clone)]
+pub(crate) struct Prelude {
+    /// Each import, as it would be written following `#import`, with its path made absolute so that it finds the same
+    /// files from every module.
+    imports: Vec<String>,
+    /// The files that the prelude imports, which don't import the prelude themselves.
+    modules: HashSet<Module>,
+    /// The files that the prelude's files import, directly or indirectly, which don't import the prelude either.
+    dependencies: HashSet<Module>,
+}
+
+impl Prelude {
+    /// Resolves the files imported by the prelude relative to the root shader.
+    pub(crate) fn resolve(
+        prelude_imports: &[String],
+        root: &Module,
+        search_paths: &SearchPaths,
+    ) -> Result<Self, ImportResolutionError> {
+        let mut modules = HashSet::new();
+        let mut resolved_imports = Vec::new();
+        for import in prelude_imports {
+            let source = format!("#import {}", import);
+            let mut resolved = source.clone();
+            for directive in lexer::directives(&source) {
+                if let Directive::Import {
+                    path,
+                    path_span,
+                    params,
+                    ..
+                } = directive
+                {
+                    modules.extend(imports::resolve_instances(
+                        root,
+                        search_paths,
+                        path,
+                        params,
+                    )?);
+                    // File paths are quoted, since they may contain whitespace
+                    let absolute = Module::absolute_request(root, search_paths, path)?;
+                    let absolute = if absolute.ends_with(".wgsl") {
+                        format!("\"{}\"", absolute)
+                    } else {
+                        absolute
+                    };
+                    resolved.replace_range(path_span, &absolute);
+                }
+            }
+            resolved_imports.push(resolved["#import ".len()..].to_owned());
+        }
+
+        Ok(Self {
+            imports: resolved_imports,
+            modules,
+            dependencies: HashSet::new(),
+        })
+    }
+
+    /// The files that the prelude imports.
+    pub(crate) fn modules(&self) -> impl Iterator<Item = &Module> {
+        self.modules.iter()
+    }
+
+    /// Leaves the given files, which the prelude's files import, without the prelude.
+    pub(crate) fn exempt(&mut self, dependencies: HashSet<Module>) {
+        self.dependencies = dependencies;
+    }
+
+    /// Gives a module's source with the prelude's imports added, unless the module is part of the prelude, is imported by
+    /// the prelude's files or opts out with `#no_prelude`. The imports are added before the first line of the source, and
+    /// any `#no_prelude` directive is replaced with equivalent whitespace.
+    pub(crate) fn apply(&self, module: &Module, mut spliced: SplicedSource) -> SplicedSource {
+        let no_prelude_spans = lexer::directives(&spliced.source)
+            .into_iter()
+            .filter_map(|directive| match directive {
+                Directive::Other {
+                    name: "no_prelude",
+                    span,
+                    ..
+                } => Some(span),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let applies = no_prelude_spans.is_empty()
+            && !self.modules.contains(module)
+            && !self.dependencies.contains(module);
+
+        for span in no_prelude_spans {
+            spliced
+                .source
+                .replace_range(span.clone(), &" ".repeat(span.len()));
+        }
+        if applies && !self.imports.is_empty() {
+            let imports = self
+                .imports
+                .iter()
+                .map(|import| format!("#import {}\n", import))
+                .collect::<String>();
+            spliced.prepend_lines(&imports);
+        }
+
+        spliced
+    }
+
+    /// Every file that the prelude imports, whether or not any shader uses the prelude.
+    pub(crate) fn files(&self) -> impl Iterator<Item = AbsoluteWGSLFilePathBuf> + '_ {
+        self.modules.iter().map(Module::path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{files::test_shaders, imports::ImportOrder};
+
+    fn resolve(prelude_imports: &[&str]) -> Result<Prelude, String> {
+        let prelude_imports = prelude_imports
+            .iter()
+            .map(|import| import.to_string())
+            .collect::<Vec<_>>();
+        let root = Module::from_path(test_shaders::shader("prelude/main.wgsl"));
+        Prelude::resolve(&prelude_imports, &root, &SearchPaths::new(None, Vec::new()))
+            .map_err(|error| error.to_string())
+    }
+
+    fn applied(prelude: &Prelude, path: &str) -> SplicedSource {
+        let module = Module::from_path(test_shaders::shader(path));
+        let spliced = module.read_spliced(&HashMap::new()).unwrap();
+        prelude.apply(&module, spliced)
+    }
+
+    #[test]
+    fn imports_the_files_found_from_the_root_shader() {
+        let prelude = resolve(&["math.wgsl::{square}"]).unwrap();
+        let math = test_shaders::shader("prelude/math.wgsl");
+
+        // `math.wgsl` isn't in the same folder as `nested/uses_math.wgsl`, but is imported by it all the same
+        let nested = applied(&prelude, "prelude/nested/uses_math.wgsl");
+        let import = format!("#import \"{}\"::{{square}}\n", math.display());
+        assert_eq!(
+            nested.source,
+            import + "fn cube(x: f32) -> f32 {\n    return square(x) * x;\n}\n"
+        );
+
+        // The imports are added before the first line
+        let file = test_shaders::shader("prelude/nested/uses_math.wgsl");
+        assert_eq!(nested.origin(1), None);
+        assert_eq!(nested.origin(2), Some((&file, 1)));
+        assert_eq!(nested.origin(4), Some((&file, 3)));
+
+        // The prelude's own files don't import the prelude
+        let math_source = applied(&prelude, "prelude/math.wgsl");
+        assert!(!math_source.moves_lines());
+    }
+
+    fn import_order(root: &str, prelude_imports: &[&str]) -> ImportOrder {
+        let prelude_imports = prelude_imports
+            .iter()
+            .map(|import| import.to_string())
+            .collect::<Vec<_>>();
+        match ImportOrder::calculate(
+            test_shaders::shader(root),
+            &SearchPaths::new(None, Vec::new()),
+            &HashMap::new(),
+            &prelude_imports,
+        ) {
+            Ok(order) => order,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    #[test]
+    fn skips_files_imported_by_the_prelude() {
+        let prelude = import_order("prelude/main.wgsl", &["angles.wgsl::{turns}"]).prelude();
+        assert!(!applied(&prelude, "prelude/angles.wgsl").moves_lines());
+        assert!(!applied(&prelude, "prelude/consts.wgsl").moves_lines());
+        assert!(applied(&prelude, "prelude/nested/uses_math.wgsl").moves_lines());
+    }
+
+    #[test]
+    fn imports_files_with_whitespace_in_their_paths() {
+        let order = import_order("prelude/with space/main.wgsl", &["tint.wgsl::{tint}"]);
+        let tint = Module::from_path(test_shaders::shader("prelude/with space/tint.wgsl"));
+        assert!(order.import_chains().contains_key(&tint));
+    }
+
+    #[test]
+    fn skips_files_which_opt_out() {
+        let prelude = resolve(&["math.wgsl"]).unwrap();
+        let opted_out = applied(&prelude, "prelude/nested/opted_out.wgsl");
+        assert_eq!(
+            opted_out.source,
+            "           \nfn half(x: f32) -> f32 {\n    return x / 2.0;\n}\n"
+        );
+        assert!(!opted_out.moves_lines());
+    }
+
+    #[test]
+    fn reports_unresolved_imports() {
+        let Err(error) = resolve(&["lighting.wgsl"]) else {
+            panic!("`lighting.wgsl` doesn't exist");
+        };
+        let root = test_shaders::shader("prelude/main.wgsl");
+        assert_eq!(
+            error,
+            format!(
+                "could not resolve import `lighting.wgsl` in file `{}`:\nlooked in location(s) `{}`",
+                root.display(),
+                root.parent().unwrap().join("lighting.wgsl").display()
+            )
+        );
+    }
+}
@@ -1,53 +1,115 @@
-use std::error::Error;
-
 use naga_to_tokenstream::{ModuleToTokens, ModuleToTokensConfig};
 
-use crate::{exports::Export, source::Sourcecode};
+use crate::{error, exports::Export, files::AbsoluteWGSLFilePathBuf, source::Sourcecode};
 
 /// The output of the transformations provided by this crate.
 pub(crate) struct ShaderResult {
     source: Sourcecode,
     module: naga::Module,
+    info: Option<naga::valid::ModuleInfo>,
 }
 
 impl ShaderResult {
     pub(crate) fn new(source: Sourcecode, module: naga::Module) -> Self {
-        Self { source, module }
+        Self {
+            source,
+            module,
+            info: None,
+        }
     }
 
-    pub(crate) fn validate(&mut self) -> Option<naga::valid::ModuleInfo> {
+    pub(crate) fn validate(&mut self) -> Option<&naga::valid::ModuleInfo> {
         let mut validator = naga::valid::Validator::new(
             naga::valid::ValidationFlags::all(),
-            naga::valid::Capabilities::all(),
+            self.source.config().capabilities,
         );
         match validator.validate(&self.module) {
-            Ok(info) => Some(info),
+            Ok(info) => {
+                self.info = Some(info);
+                self.info.as_ref()
+            }
             Err(e) => {
-                let mut e_base: &dyn Error = e.as_inner();
-                let mut message = format!("{}", e);
-                let mut error_count = 1;
-                while let Some(e) = e_base.source() {
-                    message = format!("{}: \n{}{}", message, "    ".repeat(error_count), e);
-                    e_base = e;
-                    error_count += 1;
-                }
-
-                self.source.push_error(message);
+                let diagnostic =
+                    error::format_validation_error(e, &self.module, self.source.source_map());
+                self.source.push_diagnostic(diagnostic);
 
                 None
             }
         }
     }
 
-    pub(crate) fn items(&self) -> Vec<syn::Item> {
-        let mut items = Vec::new();
+    /// Every error found while composing or validating the shader, as it is shown to the user.
+    pub(crate) fn errors(&self) -> Vec<String> {
+        self.source.errors().map(ToString::to_string).collect()
+    }
 
-        // Errors
-        for msg in self.source.errors() {
-            items.push(syn::parse_quote! {
-                compile_error!(#msg);
-            });
+    /// The composed shader, written back out as WGSL without minification, optionally excluding every entry point but
+    /// one, in the same way as the sources generated by `naga-to-tokenstream`.
+    fn unminified_source(&self, entry_point: Option<&str>) -> Option<String> {
+        let mut module = self.module.clone();
+        if let Some(entry_point) = entry_point {
+            module
+                .entry_points
+                .retain(|candidate| candidate.name == entry_point);
         }
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::empty(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(&module)
+        .ok()?;
+        naga::back::wgsl::write_string(&module, &info, naga::back::wgsl::WriterFlags::empty()).ok()
+    }
+
+    /// Swaps the minified sources generated by `naga-to-tokenstream` for unminified ones, which are the `SOURCE` of the
+    /// whole shader and the `EXCLUSIVE_SOURCE` of each entry point.
+    fn unminify(&self, items: &mut [syn::Item]) {
+        for item in items {
+            match item {
+                syn::Item::Const(item) if item.ident == "SOURCE" => {
+                    if let Some(source) = self.unminified_source(None) {
+                        item.expr = Box::new(syn::parse_quote!(#source));
+                    }
+                }
+                syn::Item::Mod(module) if module.ident == "entry_points" => {
+                    let Some((_, entry_points)) = &mut module.content else {
+                        continue;
+                    };
+                    for entry_point in entry_points {
+                        let syn::Item::Mod(entry_point) = entry_point else {
+                            continue;
+                        };
+                        let name = entry_point.ident.to_string();
+                        let Some((_, entry_point_items)) = &mut entry_point.content else {
+                            continue;
+                        };
+                        for item in entry_point_items {
+                            if let syn::Item::Const(item) = item {
+                                if item.ident == "EXCLUSIVE_SOURCE" {
+                                    if let Some(source) = self.unminified_source(Some(&name)) {
+                                        item.expr = Box::new(syn::parse_quote!(#source));
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Every shader file that was read to produce this result.
+    pub(crate) fn files(&self) -> Vec<AbsoluteWGSLFilePathBuf> {
+        std::iter::once(self.source.source_path())
+            .chain(self.source.dependents())
+            .cloned()
+            .collect()
+    }
+
+    /// The items generated for the shader. Errors aren't included, and are given by [`ShaderResult::errors`] instead.
+    pub(crate) fn items(&self) -> Vec<syn::Item> {
+        let mut items = Vec::new();
 
         // Dependencies, to re-run macro on shader change
         let origin = self
@@ -56,9 +118,15 @@ impl ShaderResult {
             .parent()
             .map(|path| path.to_path_buf())
             .expect("source should have a parent directory");
-        for dependent_path in self.source.dependents() {
-            let dependent = pathdiff::diff_paths(&**dependent_path, &origin)
-                .expect("relative path should be easy");
+        let dependents = self
+            .source
+            .dependents()
+            .map(|path| &**path)
+            .chain(self.source.data_files());
+        for dependent_path in dependents {
+            // Files outside of the crate, e.g. in `OUT_DIR`, might not have a relative path on some platforms
+            let dependent = pathdiff::diff_paths(dependent_path, &origin)
+                .unwrap_or_else(|| dependent_path.to_path_buf());
             let dependent = dependent.to_string_lossy();
             items.push(syn::parse_quote! {
                 const _: &[u8] = include_bytes!(#dependent);
@@ -78,14 +146,136 @@ impl ShaderResult {
                 Export::Struct { struct_name } => struct_name.clone(),
             })
             .collect();
+        let config = self.source.config();
         let mut module_items = self.module.to_items(ModuleToTokensConfig {
             structs_filter: Some(structs_filter),
-            gen_glam: cfg!(feature = "glam"),
-            gen_encase: cfg!(feature = "encase"),
-            gen_naga: cfg!(feature = "naga"),
+            gen_glam: config.gen_glam,
+            gen_encase: config.gen_encase,
+            gen_naga: config.gen_naga,
         });
+
+        // The `minify` feature minifies every source, so swap in the unminified sources if it was turned off
+        if cfg!(feature = "minify") && !config.minify {
+            self.unminify(&mut module_items);
+        }
         items.append(&mut module_items);
 
         items
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use super::*;
+    use crate::{
+        config::Config,
+        files::{test_shaders, AbsoluteRustFilePathBuf},
+    };
+
+    /// Includes a shader read by tests, given relative to `tests/shaders`.
+    fn include(path: &str, config: Config) -> ShaderResult {
+        let invocation = AbsoluteRustFilePathBuf::new(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("src")
+                .join("lib.rs"),
+        )
+        .unwrap();
+        let Ok(source) = Sourcecode::new(
+            invocation,
+            format!("../tests/shaders/{}", path),
+            HashMap::new(),
+            config,
+        ) else {
+            panic!("`{}` can be read", path);
+        };
+        source.complete()
+    }
+
+    #[test]
+    fn locates_parse_errors() {
+        let result = include("compose_errors/broken.wgsl", Config::default());
+        let errors = result.source.errors().collect::<Vec<_>>();
+        let [error] = errors.as_slice() else {
+            panic!("one error is found, not {}", errors.len());
+        };
+        let location = error.location.as_ref().expect("error is located");
+        let broken = test_shaders::shader("compose_errors/broken.wgsl")
+            .canonicalize()
+            .unwrap();
+        assert_eq!(location.file.canonicalize().unwrap(), broken);
+        assert_eq!(location.line, 2);
+        assert!(error
+            .to_string()
+            .starts_with(&format!("{}: wgsl parsing error: ", location)));
+    }
+
+    #[test]
+    fn locates_validation_errors_in_imported_modules() {
+        let mut result = include("source_map/main.wgsl", Config::default());
+        assert!(result.validate().is_none());
+
+        let locations = result
+            .source
+            .errors()
+            .map(|diagnostic| {
+                let location = diagnostic.location.as_ref().expect("error is located");
+                let file = location.file.canonicalize().unwrap();
+                (file, location.line, location.column)
+            })
+            .collect::<Vec<_>>();
+        let lib = test_shaders::shader("source_map/lib.wgsl")
+            .canonicalize()
+            .unwrap();
+        assert_eq!(locations, [(lib, 3, 5)]);
+    }
+
+    /// Finds the value of every `SOURCE` and `EXCLUSIVE_SOURCE` constant in a set of generated items.
+    fn sources(items: &[syn::Item], found: &mut Vec<String>) {
+        for item in items {
+            match item {
+                syn::Item::Const(item)
+                    if item.ident == "SOURCE" || item.ident == "EXCLUSIVE_SOURCE" =>
+                {
+                    let syn::Expr::Lit(syn::ExprLit {
+                        lit: syn::Lit::Str(source),
+                        ..
+                    }) = &*item.expr
+                    else {
+                        panic!("sources are string literals");
+                    };
+                    found.push(source.value());
+                }
+                syn::Item::Mod(module) => {
+                    if let Some((_, items)) = &module.content {
+                        sources(items, found);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_every_source_unminified_when_configured() {
+        let config = Config {
+            minify: false,
+            ..Config::default()
+        };
+        let mut result = include("minify/main.wgsl", config);
+        assert!(result.validate().is_some());
+
+        let mut found = Vec::new();
+        sources(&result.items(), &mut found);
+        // The whole shader, and the shader excluding all but each of its two entry points
+        assert_eq!(found.len(), 3);
+        for source in found {
+            assert!(
+                source.contains("fn brighten(color: vec4<f32>) -> vec4<f32> {\n    return "),
+                "`{}` is minified",
+                source
+            );
+        }
+    }
+}
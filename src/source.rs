@@ -1,83 +1,119 @@
 use std::{
     collections::{HashMap, HashSet},
     ffi::OsStr,
+    path::PathBuf,
 };
 
-use naga_oil::compose::Composer;
+use naga_oil::compose::{Composer, ShaderDefValue};
 
 use crate::{
+    config::Config,
+    error::Diagnostic,
     exports::{strip_exports, Export},
-    files::{AbsoluteRustFilePathBuf, AbsoluteRustRootPathBuf, AbsoluteWGSLFilePathBuf},
+    files::{self, AbsoluteRustFilePathBuf, AbsoluteWGSLFilePathBuf, SearchPaths},
     imports::ImportOrder,
+    includes::SplicedSource,
+    module::Module,
+    prelude::Prelude,
     result::ShaderResult,
+    source_map::SourceMap,
 };
 
 /// Shader sourcecode generated from the token stream provided
 pub(crate) struct Sourcecode {
     exports: HashSet<Export>,
     requested_path_input: String,
+    expanded_path: String,
     source_path: AbsoluteWGSLFilePathBuf,
     invocation_path: AbsoluteRustFilePathBuf,
-    project_root: Option<AbsoluteRustRootPathBuf>,
-    errors: Vec<String>,
+    search_paths: SearchPaths,
+    shader_defs: HashMap<String, ShaderDefValue>,
+    config: Config,
+    errors: Vec<Diagnostic>,
     dependents: Vec<AbsoluteWGSLFilePathBuf>,
+    /// Files embedded into shaders with `#embed`.
+    data_files: Vec<PathBuf>,
+    /// The sources of the modules composed into the shader, to show validation errors in.
+    source_map: SourceMap,
 }
 
 impl Sourcecode {
+    /// Finds the shader requested by an invocation of the macro, erroring if it can't be read.
     pub(crate) fn new(
         invocation_path: AbsoluteRustFilePathBuf,
         requested_path_input: String,
-    ) -> Self {
-        // Interpret as relative to invoking file
+        shader_defs: HashMap<String, ShaderDefValue>,
+        config: Config,
+    ) -> Result<Self, String> {
+        // Expand environment variables, e.g. `$OUT_DIR`, then interpret as relative to invoking file
+        let expanded_path = files::expand_env_vars(&requested_path_input).map_err(|variable| {
+            format!(
+                "could not find import `{}`: environment variable `{}` is not set",
+                requested_path_input, variable
+            )
+        })?;
         let source_path = invocation_path
             .parent()
             .expect("files have parent directories")
-            .join(&requested_path_input);
+            .join(&expanded_path);
         if !source_path.is_file() {
             if source_path.exists() {
-                panic!(
+                return Err(format!(
                     "could not find import `{}`: `{}` exists but is not a file",
                     requested_path_input,
                     source_path.display()
-                )
+                ));
             }
-            panic!(
+            return Err(format!(
                 "could not find import `{}`: `{}` does not exist",
                 requested_path_input,
                 source_path.display()
-            );
+            ));
         }
-        assert!(source_path.is_absolute());
 
         if source_path.extension() != Some(OsStr::new("wgsl")) {
-            panic!(
+            return Err(format!(
                 "file `{}` does not have the required `.wgsl` extension",
                 requested_path_input,
-            );
+            ));
         };
 
-        let source_path = AbsoluteWGSLFilePathBuf::new(source_path);
+        let source_path = AbsoluteWGSLFilePathBuf::new(source_path)?;
 
         // Calculate top level exports
-        let root_src = std::fs::read_to_string(&*source_path).expect("asserted was file");
+        let root_src = std::fs::read_to_string(&*source_path)
+            .map_err(|e| format!("could not read `{}`: {}", source_path.display(), e))?;
         let (_, exports) = strip_exports(&root_src);
 
-        let project_root = invocation_path.get_source_rust_root();
-        Self {
+        let search_paths = SearchPaths::new(
+            invocation_path.get_source_rust_root(),
+            config.include_dirs.clone(),
+        );
+        Ok(Self {
             requested_path_input,
+            expanded_path,
             source_path,
             invocation_path,
-            project_root,
+            search_paths,
             exports,
+            shader_defs,
+            config,
             errors: Vec::new(),
             dependents: Vec::new(),
-        }
+            data_files: Vec::new(),
+            source_map: SourceMap::default(),
+        })
     }
 
     /// Traverses the imports in each file, starting with the file given by this object, to give all of the files required
     /// and the order in which they need to be processed.
     fn find_import_order(&mut self) -> Option<ImportOrder> {
-        match ImportOrder::calculate(self.source_path.clone(), self.project_root.as_ref()) {
+        match ImportOrder::calculate(
+            self.source_path.clone(),
+            &self.search_paths,
+            &self.shader_defs,
+            &self.config.prelude,
+        ) {
             Ok(order) => Some(order),
             Err(err) => {
                 self.push_error(format!("{}", err));
@@ -89,32 +125,71 @@ impl Sourcecode {
     /// Uses naga_oil to process includes
     fn compose(&mut self) -> Option<naga::Module> {
         let mut composer = Composer::default();
-        composer.capabilities = naga::valid::Capabilities::all();
+        composer.capabilities = self.config.capabilities;
         composer.validate = true;
 
-        let mut shader_defs = HashMap::new();
-        if cfg!(debug_assertions) {
-            shader_defs.insert(
-                "__DEBUG".to_string(),
-                naga_oil::compose::ShaderDefValue::Bool(true),
-            );
-        }
+        let shader_defs = self.shader_defs.clone();
 
         // Calculate import order
         let import_order = self.find_import_order()?;
 
         // Calculate names of imports
         let reduced_names = import_order.reduced_names();
+        let re_exports = import_order.re_exports();
+        let prelude = import_order.prelude();
+
+        // Notes tracing how each import was reached from the invocation of the macro, by the name of each module
+        let import_notes = import_order
+            .import_chains()
+            .into_iter()
+            .map(|(module, chain)| {
+                let note = format!(
+                    "\nnote: imported from {} → invocation in `{}`",
+                    chain,
+                    self.invocation_path.display()
+                );
+                (reduced_names[&module].clone(), note)
+            })
+            .collect::<HashMap<_, _>>();
+
+        // Calculate the shader defs of each import, including those given by `#define`s in imported files
+        let module_defs = match import_order.module_defs(&shader_defs) {
+            Ok(module_defs) => module_defs,
+            Err(errors) => {
+                for error in errors {
+                    self.push_error(error);
+                }
+                return None;
+            }
+        };
+
+        // The prelude's files are depended on even if every shader opts out of it
+        for file in prelude.files() {
+            self.push_dependent(file);
+        }
+
+        // The sources of modules which include other files or have the prelude added, by the name of each module or the
+        // path of the root module
+        let mut includes = HashMap::new();
 
         // Add imports in order to naga-oil
         let (imports, root) = import_order.modules();
         for import in imports {
-            self.dependents.push(import.path());
+            self.push_dependent(import.path());
+            self.track_includes(
+                &import,
+                reduced_names[&import].clone(),
+                &module_defs[&import],
+                &prelude,
+                &mut includes,
+            );
 
             let desc = import.to_composable_module_descriptor(
                 &reduced_names,
-                self.project_root.as_ref(),
-                shader_defs.clone(),
+                &re_exports,
+                &prelude,
+                &self.search_paths,
+                module_defs[&import].clone(),
             );
             let desc = match desc {
                 Ok(desc) => desc,
@@ -126,9 +201,16 @@ impl Sourcecode {
                 }
             };
 
+            self.push_data_files(desc.data_files());
+
             let res = composer.add_composable_module(desc.borrow_composable_descriptor());
             if let Err(e) = res {
-                self.push_error(crate::error::format_compose_error(e, &composer));
+                self.push_diagnostic(crate::error::format_compose_error(
+                    e,
+                    &composer,
+                    &includes,
+                    &import_notes,
+                ));
             }
         }
 
@@ -137,8 +219,20 @@ impl Sourcecode {
         }
 
         // Add main module to link everything
-        let desc =
-            root.to_naga_module_descriptor(&reduced_names, self.project_root.as_ref(), shader_defs);
+        self.track_includes(
+            &root,
+            root.path().to_string_lossy().to_string(),
+            &shader_defs,
+            &prelude,
+            &mut includes,
+        );
+        let desc = root.to_naga_module_descriptor(
+            &reduced_names,
+            &re_exports,
+            &prelude,
+            &self.search_paths,
+            shader_defs,
+        );
         let desc = match desc {
             Ok(desc) => desc,
             Err(errors) => {
@@ -148,12 +242,26 @@ impl Sourcecode {
                 return None;
             }
         };
+        self.push_data_files(desc.data_files());
         let res = composer.make_naga_module(desc.borrow_module_descriptor());
 
         match res {
-            Ok(module) => Some(module),
+            Ok(module) => {
+                self.source_map = SourceMap::new(
+                    &composer,
+                    root.path().to_path_buf(),
+                    desc.borrow_module_descriptor().source.to_owned(),
+                    includes,
+                );
+                Some(module)
+            }
             Err(e) => {
-                self.push_error(crate::error::format_compose_error(e, &composer));
+                self.push_diagnostic(crate::error::format_compose_error(
+                    e,
+                    &composer,
+                    &includes,
+                    &import_notes,
+                ));
 
                 None
             }
@@ -166,11 +274,59 @@ impl Sourcecode {
         ShaderResult::new(self, module)
     }
 
+    /// Records a file which should trigger recompilation when changed. A file imported with different parameters is
+    /// composed once per instance, but only depended on once.
+    fn push_dependent(&mut self, path: AbsoluteWGSLFilePathBuf) {
+        if !self.dependents.contains(&path) {
+            self.dependents.push(path);
+        }
+    }
+
+    /// Records files embedded into a module as dependents.
+    fn push_data_files(&mut self, data_files: &[PathBuf]) {
+        for file in data_files {
+            if !self.data_files.contains(file) {
+                self.data_files.push(file.clone());
+            }
+        }
+    }
+
+    /// Records the files that a module includes as dependents, keeping the module's spliced source, with the prelude
+    /// added, so that errors can be mapped back onto the lines of the files that they were written in.
+    fn track_includes(
+        &mut self,
+        module: &Module,
+        name: String,
+        shader_defs: &HashMap<String, ShaderDefValue>,
+        prelude: &Prelude,
+        includes: &mut HashMap<String, SplicedSource>,
+    ) {
+        let Ok(spliced) = module.read_spliced(shader_defs) else {
+            return;
+        };
+        let spliced = prelude.apply(module, spliced);
+        if !spliced.moves_lines() {
+            return;
+        }
+        for file in spliced.included_files() {
+            self.push_dependent(file.clone());
+        }
+        includes.insert(name, spliced);
+    }
+
     pub(crate) fn push_error(&mut self, message: String) {
-        self.errors.push(message)
+        self.push_diagnostic(Diagnostic::new(message))
     }
 
-    pub(crate) fn errors(&self) -> impl Iterator<Item = &String> {
+    pub(crate) fn push_diagnostic(&mut self, diagnostic: Diagnostic) {
+        self.errors.push(diagnostic)
+    }
+
+    pub(crate) fn source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    pub(crate) fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
         self.errors.iter()
     }
 
@@ -178,15 +334,77 @@ impl Sourcecode {
         self.dependents.iter()
     }
 
+    pub(crate) fn data_files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.data_files.iter()
+    }
+
+    pub(crate) fn source_path(&self) -> &AbsoluteWGSLFilePathBuf {
+        &self.source_path
+    }
+
+    /// The path given to the macro, with any environment variables expanded.
     pub(crate) fn requested_path(&self) -> &str {
-        &self.requested_path_input
+        &self.expanded_path
     }
 
     pub(crate) fn invocation_path(&self) -> &AbsoluteRustFilePathBuf {
         &self.invocation_path
     }
 
+    pub(crate) fn config(&self) -> &Config {
+        &self.config
+    }
+
     pub(crate) fn exports(&self) -> &HashSet<Export> {
         &self.exports
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invocation() -> AbsoluteRustFilePathBuf {
+        AbsoluteRustFilePathBuf::new(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("src")
+                .join("lib.rs"),
+        )
+        .unwrap()
+    }
+
+    fn error(requested: &str) -> String {
+        let Err(error) = Sourcecode::new(
+            invocation(),
+            requested.to_owned(),
+            HashMap::new(),
+            Config::default(),
+        ) else {
+            panic!("`{}` can't be included", requested);
+        };
+        error
+    }
+
+    #[test]
+    fn reports_unreadable_shaders() {
+        let src = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src");
+        assert_eq!(
+            error("missing.wgsl"),
+            format!(
+                "could not find import `missing.wgsl`: `{}` does not exist",
+                src.join("missing.wgsl").display()
+            )
+        );
+        assert_eq!(
+            error("../tests"),
+            format!(
+                "could not find import `../tests`: `{}` exists but is not a file",
+                src.join("../tests").display()
+            )
+        );
+        assert_eq!(
+            error("../Cargo.toml"),
+            "file `../Cargo.toml` does not have the required `.wgsl` extension"
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use std::{cell::OnceCell, collections::HashMap, ops::Range, path::PathBuf};
+
+use naga_oil::compose::Composer;
+use regex::Regex;
+
+use crate::{includes::SplicedSource, lexer};
+
+/// The number of bits that `naga_oil` shifts the index of a module by when adding it to the spans of the module's items,
+/// so that spans from every module can be told apart in the composed module.
+const SPAN_SHIFT: usize = 21;
+
+/// The source of one of the modules composed into a shader, as it was given to `naga_oil`.
+pub(crate) struct ModuleSource {
+    pub(crate) name: String,
+    pub(crate) file: PathBuf,
+    pub(crate) source: String,
+    /// The files spliced into the module with `#include`, if there are any.
+    pub(crate) spliced: Option<SplicedSource>,
+}
+
+/// Maps spans in a composed module back onto the sources of the modules that they came from.
+#[derive(Default)]
+pub(crate) struct SourceMap {
+    /// The source of each module, by the index that `naga_oil` gave it. The root module has index 0.
+    modules: HashMap<usize, ModuleSource>,
+    /// The offsets given by [`SourceMap::find_offsets`], which are found when first needed. A source map is only used
+    /// with the composed module that it was made for, so these never change.
+    offsets: OnceCell<HashMap<usize, Vec<(usize, isize)>>>,
+}
+
+lazy_static::lazy_static! {
+    /// Matches the declaration of a function, global variable or constant, including any attributes before it, capturing
+    /// the keyword and the name declared.
+    static ref DECLARATION_REGEX: Regex = Regex::new(
+        r"(?:@[A-Za-z_]\w*\s*(?:\([^)]*\)\s*)?)*\b(fn|var|const)\b\s*(?:<[^>]*>\s*)?([A-Za-z_]\w*)\b"
+    )
+    .expect("pattern is valid");
+}
+
+/// Finds where each declaration starts in a module's source, including any attributes before it, by the keyword and
+/// name that it is declared with. Names which are declared more than once with the same keyword are left out, since
+/// the declarations can't be told apart.
+fn declaration_starts(source: &str) -> HashMap<(&str, &str), usize> {
+    let mut starts = HashMap::new();
+    let mut repeated = Vec::new();
+    for captures in DECLARATION_REGEX.captures_iter(source) {
+        let start = captures.get(0).expect("whole match is present").start();
+        let key = (
+            captures.get(1).expect("keyword is captured").as_str(),
+            captures.get(2).expect("name is captured").as_str(),
+        );
+        if starts.insert(key, start).is_some() {
+            repeated.push(key);
+        }
+    }
+    for key in repeated {
+        starts.remove(&key);
+    }
+    starts
+}
+
+/// Gives the name that an item was declared with in its module, removing the suffix that `naga_oil` adds to the names
+/// of items from imported modules.
+fn declared_name(name: &str) -> &str {
+    name.find("X_naga_oil_mod_X")
+        .map_or(name, |end| &name[..end])
+}
+
+impl SourceMap {
+    /// Records the sources of the modules added to a composer, given the root module's file and source, and the spliced
+    /// sources of the modules which include other files, by the name of each module or the path of the root module.
+    pub(crate) fn new(
+        composer: &Composer,
+        root_file: PathBuf,
+        root_source: String,
+        mut includes: HashMap<String, SplicedSource>,
+    ) -> Self {
+        let mut modules = HashMap::new();
+        for (index, name) in &composer.module_index {
+            let Some(module_set) = composer.module_sets.get(name) else {
+                continue;
+            };
+            modules.insert(
+                *index,
+                ModuleSource {
+                    name: name.clone(),
+                    file: PathBuf::from(&module_set.file_path),
+                    source: module_set.sanitized_source.clone(),
+                    spliced: includes.remove(name),
+                },
+            );
+        }
+
+        let root_name = root_file.to_string_lossy().to_string();
+        modules.insert(
+            0,
+            ModuleSource {
+                spliced: includes.remove(&root_name),
+                name: root_name,
+                file: root_file,
+                source: root_source,
+            },
+        );
+
+        Self {
+            modules,
+            offsets: OnceCell::new(),
+        }
+    }
+
+    /// Finds the offset that `naga_oil` parsed each module's source at, which is after a header declaring the items that
+    /// the module imports. The offsets are found by comparing the spans of the composed module's named items against
+    /// where they are declared in each source, by the index of each module.
+    fn find_offsets(&self, module: &naga::Module) -> HashMap<usize, Vec<(usize, isize)>> {
+        let functions = module.functions.iter().filter_map(|(handle, function)| {
+            Some((
+                "fn",
+                function.name.as_deref()?,
+                module.functions.get_span(handle),
+            ))
+        });
+        let globals = module
+            .global_variables
+            .iter()
+            .filter_map(|(handle, global)| {
+                Some((
+                    "var",
+                    global.name.as_deref()?,
+                    module.global_variables.get_span(handle),
+                ))
+            });
+        let constants = module.constants.iter().filter_map(|(handle, constant)| {
+            Some((
+                "const",
+                constant.name.as_deref()?,
+                module.constants.get_span(handle),
+            ))
+        });
+
+        // Declarations which have been commented out aren't anchors
+        let code = self
+            .modules
+            .iter()
+            .map(|(index, module)| (*index, lexer::strip_comments(&module.source)))
+            .collect::<HashMap<_, _>>();
+        let declarations = code
+            .iter()
+            .map(|(index, code)| (*index, declaration_starts(code)))
+            .collect::<HashMap<_, _>>();
+
+        let mut offsets = HashMap::<usize, Vec<(usize, isize)>>::new();
+        for (keyword, name, span) in functions.chain(globals).chain(constants) {
+            let Some(range) = span.to_range() else {
+                continue;
+            };
+            let index = range.start >> SPAN_SHIFT;
+            let start = range.start & ((1 << SPAN_SHIFT) - 1);
+            let Some(declared) = declarations
+                .get(&index)
+                .and_then(|declarations| declarations.get(&(keyword, declared_name(name))))
+            else {
+                continue;
+            };
+            offsets
+                .entry(index)
+                .or_default()
+                .push((start, start as isize - *declared as isize));
+        }
+        for anchors in offsets.values_mut() {
+            anchors.sort();
+        }
+        offsets
+    }
+
+    /// Finds the module that a span in a composed module came from, and the range that it covers in that module's source.
+    /// Each span is located relative to the closest item declared before it, since `naga_oil`'s preprocessing may change
+    /// the length of the lines before it.
+    pub(crate) fn locate(
+        &self,
+        module: &naga::Module,
+        span: naga::Span,
+    ) -> Option<(&ModuleSource, Range<usize>)> {
+        let range = span.to_range()?;
+        let index = range.start >> SPAN_SHIFT;
+        let source = self.modules.get(&index)?;
+        let mask = (1 << SPAN_SHIFT) - 1;
+        let (start, end) = (range.start & mask, range.end & mask);
+
+        let offsets = self.offsets.get_or_init(|| self.find_offsets(module));
+        let anchors = offsets.get(&index)?;
+        let (_, offset) = anchors
+            .iter()
+            .rev()
+            .find(|(anchor, _)| *anchor <= start)
+            .or(anchors.first())?;
+
+        let start = (start as isize - offset).clamp(0, source.source.len() as isize) as usize;
+        let end =
+            (end as isize - offset).clamp(start as isize, source.source.len() as isize) as usize;
+        Some((source, start..end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_declarations_with_attributes() {
+        let source = "@group(0) @binding(0) var<uniform> light: Light;\nfn shade() {}\nconst A = 1;\nfn f() { const A = 2; }\n";
+        let starts = declaration_starts(source);
+        assert_eq!(starts.get(&("var", "light")), Some(&0));
+        assert_eq!(
+            starts.get(&("fn", "shade")),
+            source.find("fn shade").as_ref()
+        );
+        assert_eq!(starts.get(&("fn", "f")), source.find("fn f").as_ref());
+        assert_eq!(starts.get(&("const", "A")), None);
+    }
+
+    #[test]
+    fn removes_naga_oil_decoration() {
+        assert_eq!(declared_name("shade"), "shade");
+        assert_eq!(
+            declared_name("shadeX_naga_oil_mod_XNRUWELTXM5ZWYX"),
+            "shade"
+        );
+    }
+}
@@ -0,0 +1,136 @@
+/// The most suggestions given for a single misspelt name.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Gives the number of single character insertions, deletions and substitutions needed to turn one string into another.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+    for (i, a_char) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// Removes any leading `./` and `../` components from a path.
+fn strip_relative_prefix(path: &str) -> &str {
+    let mut path = path;
+    while let Some(rest) = path.strip_prefix("../").or_else(|| path.strip_prefix("./")) {
+        path = rest;
+    }
+    path
+}
+
+/// Measures how close a candidate is to a misspelt name, giving `None` if it is too different to suggest. Differences
+/// in case alone, and missing or extra `../` at the start of a path, count as the closest possible.
+fn closeness(requested: &str, candidate: &str) -> Option<(usize, usize)> {
+    let requested_lower = requested.to_lowercase();
+    let candidate_lower = candidate.to_lowercase();
+    let exact_distance = edit_distance(requested, candidate);
+    if strip_relative_prefix(&requested_lower) == strip_relative_prefix(&candidate_lower) {
+        return Some((0, exact_distance));
+    }
+
+    let distance = edit_distance(&requested_lower, &candidate_lower);
+    let max_distance = (requested.chars().count() / 3).max(1);
+    (distance <= max_distance).then_some((distance, exact_distance))
+}
+
+/// Finds the candidates closest to a name which couldn't be found, closest first.
+pub(crate) fn closest(
+    requested: &str,
+    candidates: impl IntoIterator<Item = String>,
+) -> Vec<String> {
+    let mut scored = candidates
+        .into_iter()
+        .filter(|candidate| candidate != requested)
+        .filter_map(|candidate| Some((closeness(requested, &candidate)?, candidate)))
+        .collect::<Vec<_>>();
+    scored.sort();
+    scored.dedup_by(|(_, a), (_, b)| a == b);
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// Formats suggestions as a line to end an error with, which is empty if there are none.
+pub(crate) fn help(suggestions: &[String]) -> String {
+    match suggestions {
+        [] => String::new(),
+        [suggestion] => format!("\nhelp: did you mean `{}`?", suggestion),
+        suggestions => format!(
+            "\nhelp: did you mean one of {}?",
+            suggestions
+                .iter()
+                .map(|suggestion| format!("`{}`", suggestion))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn closest_to(requested: &str, candidates: &[&str]) -> Vec<String> {
+        closest(
+            requested,
+            candidates.iter().map(|candidate| candidate.to_string()),
+        )
+    }
+
+    #[test]
+    fn counts_edits() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+    }
+
+    #[test]
+    fn suggests_close_names_closest_first() {
+        assert_eq!(
+            closest_to("my_fnn", &["my_fn", "my_fun", "other_fn", "my_fnn"]),
+            ["my_fn", "my_fun"]
+        );
+        assert!(closest_to("light", &["shadow", "noise"]).is_empty());
+    }
+
+    #[test]
+    fn suggests_wrong_case_and_missing_parent_folders_first() {
+        assert_eq!(
+            closest_to("Utils.wgsl", &["utils.wgsl", "util.wgsl"]),
+            ["utils.wgsl", "util.wgsl"]
+        );
+        assert_eq!(
+            closest_to(
+                "shared/utils.wgsl",
+                &["shared/util.wgsl", "../shared/utils.wgsl"]
+            ),
+            ["../shared/utils.wgsl", "shared/util.wgsl"]
+        );
+    }
+
+    #[test]
+    fn gives_at_most_three_suggestions() {
+        let suggestions = closest_to("abc", &["abd", "abe", "abf", "abg"]);
+        assert_eq!(suggestions, ["abd", "abe", "abf"]);
+    }
+
+    #[test]
+    fn formats_help() {
+        assert_eq!(help(&[]), "");
+        assert_eq!(help(&["a".to_owned()]), "\nhelp: did you mean `a`?");
+        assert_eq!(
+            help(&["a".to_owned(), "b".to_owned()]),
+            "\nhelp: did you mean one of `a`, `b`?"
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::{HashMap, HashSet};
+
+use naga_oil::compose::ShaderDefValue;
+use quote::{format_ident, ToTokens};
+
+use crate::{args::VariantAxis, conditionals};
+
+/// A single combination of variant values, e.g. `SHADOWS = true, SAMPLES = 4`. The default has no values, and is the
+/// only variant of a shader which isn't given any variants.
+#[derive(Default)]
+pub(crate) struct Variant {
+    values: Vec<(String, ShaderDefValue)>,
+}
+
+impl Variant {
+    /// Gives every combination of the values of the variant axes given, in the order that they were given.
+    pub(crate) fn combinations(axes: &[VariantAxis]) -> Vec<Self> {
+        let mut variants = vec![Self::default()];
+        for axis in axes {
+            let name = axis.name.to_string();
+            let mut next = Vec::new();
+            for variant in variants {
+                for value in &axis.values {
+                    let mut values = variant.values.clone();
+                    values.push((name.clone(), *value));
+                    next.push(Self { values });
+                }
+            }
+            variants = next;
+        }
+        variants
+    }
+
+    pub(crate) fn shader_defs(&self) -> HashMap<String, ShaderDefValue> {
+        self.values.iter().cloned().collect()
+    }
+
+    /// The name of the module containing the items generated for this variant, e.g. `shadows_true_samples_4`.
+    fn module_ident(&self) -> syn::Ident {
+        let name = self
+            .values
+            .iter()
+            .map(|(name, value)| format!("{}_{}", name, conditionals::ident_value(value)))
+            .collect::<Vec<_>>()
+            .join("_")
+            .to_lowercase();
+        format_ident!("{}", name)
+    }
+
+    /// The name of the key enum variant for this variant, e.g. `ShadowsTrueSamples4`.
+    fn key_ident(&self) -> syn::Ident {
+        let name = self
+            .values
+            .iter()
+            .flat_map(|(name, value)| {
+                let value = conditionals::ident_value(value);
+                name.split('_')
+                    .chain(value.split('_'))
+                    .map(|part| {
+                        let mut chars = part.chars();
+                        match chars.next() {
+                            Some(first) => {
+                                first.to_uppercase().collect::<String>()
+                                    + &chars.as_str().to_lowercase()
+                            }
+                            None => String::new(),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<String>();
+        format_ident!("{}", name)
+    }
+
+    fn description(&self) -> String {
+        self.values
+            .iter()
+            .map(|(name, value)| format!("`{} = {}`", name, conditionals::display_value(value)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Collates the errors found in each variant of a shader, giving each distinct error once. Errors found in every variant,
+/// such as a missing file, are given as they are, whereas the others are prefixed with the keys of the variants that
+/// they were found in.
+pub(crate) fn variant_errors(found: Vec<(&Variant, Vec<String>)>) -> Vec<String> {
+    let mut distinct = Vec::<(String, Vec<&Variant>)>::new();
+    for (variant, errors) in &found {
+        for error in errors {
+            match distinct.iter_mut().find(|(distinct, _)| distinct == error) {
+                Some((_, found_in)) => {
+                    if !found_in.iter().any(|found| std::ptr::eq(*found, *variant)) {
+                        found_in.push(variant);
+                    }
+                }
+                None => distinct.push((error.clone(), vec![variant])),
+            }
+        }
+    }
+
+    distinct
+        .into_iter()
+        .map(|(error, found_in)| {
+            if found_in.len() == found.len() {
+                return error;
+            }
+            let keys = found_in
+                .iter()
+                .map(|variant| format!("`Variant::{}`", variant.key_ident()))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let noun = if found_in.len() == 1 {
+                "variant"
+            } else {
+                "variants"
+            };
+            format!("in {} {}: {}", noun, keys, error)
+        })
+        .collect()
+}
+
+/// Takes the contents of the `types` module out of a set of generated items.
+fn take_types(items: &mut [syn::Item]) -> Vec<syn::Item> {
+    for item in items {
+        if let syn::Item::Mod(module) = item {
+            if module.ident == "types" {
+                if let Some((_, content)) = &mut module.content {
+                    return std::mem::take(content);
+                }
+            }
+        }
+    }
+    Vec::new()
+}
+
+fn type_name(item: &syn::Item) -> Option<String> {
+    match item {
+        syn::Item::Struct(item) => Some(item.ident.to_string()),
+        _ => None,
+    }
+}
+
+fn referenced_idents(tokens: proc_macro2::TokenStream, idents: &mut HashSet<String>) {
+    for token in tokens {
+        match token {
+            proc_macro2::TokenTree::Ident(ident) => {
+                idents.insert(ident.to_string());
+            }
+            proc_macro2::TokenTree::Group(group) => referenced_idents(group.stream(), idents),
+            _ => {}
+        }
+    }
+}
+
+/// Finds the names of the types which are defined identically in every variant, and which only refer to other such types.
+fn shared_type_names(types: &[Vec<syn::Item>]) -> HashSet<String> {
+    let definitions = types
+        .iter()
+        .map(|types| {
+            types
+                .iter()
+                .filter_map(|item| Some((type_name(item)?, item.to_token_stream().to_string())))
+                .collect::<HashMap<_, _>>()
+        })
+        .collect::<Vec<_>>();
+    let all_names = definitions
+        .iter()
+        .flat_map(|definitions| definitions.keys().cloned())
+        .collect::<HashSet<_>>();
+
+    let (first, rest) = definitions
+        .split_first()
+        .expect("there is always at least one variant");
+    let mut shared = first
+        .iter()
+        .filter(|(name, definition)| {
+            rest.iter()
+                .all(|other| other.get(*name) == Some(*definition))
+        })
+        .map(|(name, _)| name.clone())
+        .collect::<HashSet<_>>();
+
+    // A shared type can't refer to a type which differs between variants
+    loop {
+        let unshared = all_names
+            .difference(&shared)
+            .cloned()
+            .collect::<HashSet<_>>();
+        let before = shared.len();
+        shared.retain(|name| {
+            let item = types[0]
+                .iter()
+                .find(|item| type_name(item).as_ref() == Some(name))
+                .expect("shared types are present in every variant");
+            let mut idents = HashSet::new();
+            referenced_idents(item.to_token_stream(), &mut idents);
+            idents.is_disjoint(&unshared)
+        });
+        if shared.len() == before {
+            return shared;
+        }
+    }
+}
+
+/// Checks if the items generated for a variant include its source, which they don't if it couldn't be read.
+fn has_source(items: &[syn::Item]) -> bool {
+    items
+        .iter()
+        .any(|item| matches!(item, syn::Item::Const(item) if item.ident == "SOURCE"))
+}
+
+/// Collates the items generated for each variant of a shader into a module per variant, a key enum
+/// and a lookup of the source of each variant. Types which are identical across every variant are emitted once.
+/// The key enum and lookups refer to the source of every variant, so are only emitted if every variant has one.
+pub(crate) fn variant_items(variants: Vec<(Variant, Vec<syn::Item>)>) -> Vec<syn::Item> {
+    let (variants, mut variant_items): (Vec<_>, Vec<_>) = variants.into_iter().unzip();
+    let complete = variant_items.iter().all(|items| has_source(items));
+
+    // Deduplicate types
+    let types = variant_items
+        .iter_mut()
+        .map(|items| take_types(items))
+        .collect::<Vec<_>>();
+    let shared_names = shared_type_names(&types);
+    let mut shared_types = Vec::new();
+    for (i, (variant_types, items)) in types.into_iter().zip(&mut variant_items).enumerate() {
+        let (shared, unshared): (Vec<_>, Vec<_>) = variant_types
+            .into_iter()
+            .partition(|item| type_name(item).is_some_and(|name| shared_names.contains(&name)));
+        if i == 0 {
+            shared_types = shared;
+        }
+
+        for item in items.iter_mut() {
+            if let syn::Item::Mod(module) = item {
+                if module.ident == "types" {
+                    module.content = Some((
+                        syn::token::Brace::default(),
+                        std::iter::once(syn::parse_quote! {
+                            pub use super::super::types::*;
+                        })
+                        .chain(unshared.clone())
+                        .collect(),
+                    ));
+                }
+            }
+        }
+    }
+
+    let mut items: Vec<syn::Item> = Vec::new();
+    items.push(syn::parse_quote! {
+        #[allow(unused)]
+        #[doc = "Equivalent Rust definitions of the types which are identical in every variant of this module."]
+        pub mod types {
+            #(#shared_types)*
+        }
+    });
+
+    let keys = variants
+        .iter()
+        .map(|variant| variant.key_ident())
+        .collect::<Vec<_>>();
+    let docs = variants
+        .iter()
+        .map(|variant| format!("The variant with {}.", variant.description()))
+        .collect::<Vec<_>>();
+    let modules = variants
+        .iter()
+        .map(|variant| variant.module_ident())
+        .collect::<Vec<_>>();
+
+    if complete {
+        items.push(syn::parse_quote! {
+            #[doc = "A key identifying one of the variants of this shader."]
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+            pub enum Variant {
+                #(
+                    #[doc = #docs]
+                    #keys,
+                )*
+            }
+        });
+        items.push(syn::parse_quote! {
+            impl Variant {
+                #[doc = "Every variant of this shader."]
+                pub const ALL: &'static [Variant] = &[#(Variant::#keys),*];
+            }
+        });
+        items.push(syn::parse_quote! {
+            #[doc = "The sourcecode for every variant of the shader, as constant strings."]
+            pub const SOURCES: &[(Variant, &str)] = &[#((Variant::#keys, #modules::SOURCE)),*];
+        });
+        items.push(syn::parse_quote! {
+            #[doc = "Gets the sourcecode for a variant of the shader."]
+            pub const fn source(key: Variant) -> &'static str {
+                match key {
+                    #(Variant::#keys => #modules::SOURCE,)*
+                }
+            }
+        });
+    }
+
+    for ((variant, module), module_items) in variants.iter().zip(&modules).zip(variant_items) {
+        let doc = format!(
+            "Information about the variant of the shader with {}.",
+            variant.description()
+        );
+        items.push(syn::parse_quote! {
+            #[doc = #doc]
+            pub mod #module {
+                #(#module_items)*
+            }
+        });
+    }
+
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn axis(name: &str, values: &str) -> VariantAxis {
+        syn::parse_str(&format!("{} = [{}]", name, values)).expect("valid variant axis")
+    }
+
+    fn item_names(items: &[syn::Item]) -> Vec<String> {
+        items
+            .iter()
+            .filter_map(|item| match item {
+                syn::Item::Mod(item) => Some(item.ident.to_string()),
+                syn::Item::Enum(item) => Some(item.ident.to_string()),
+                syn::Item::Const(item) => Some(item.ident.to_string()),
+                syn::Item::Fn(item) => Some(item.sig.ident.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn names_every_combination() {
+        let variants =
+            Variant::combinations(&[axis("SHADOWS", "true, false"), axis("OFFSET", "-1, 2")]);
+        let modules = variants
+            .iter()
+            .map(|variant| variant.module_ident().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            modules,
+            [
+                "shadows_true_offset_neg_1",
+                "shadows_true_offset_2",
+                "shadows_false_offset_neg_1",
+                "shadows_false_offset_2",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_distinct_errors_of_every_variant() {
+        let variants = Variant::combinations(&[axis("SAMPLES", "1u, 4u, 8u")]);
+        let missing = "could not find import `missing.wgsl`".to_owned();
+        let errors = variant_errors(vec![
+            (&variants[0], vec![missing.clone()]),
+            (
+                &variants[1],
+                vec![missing.clone(), "too many samples".to_owned()],
+            ),
+            (
+                &variants[2],
+                vec![missing.clone(), "too many samples".to_owned()],
+            ),
+        ]);
+        assert_eq!(
+            errors,
+            [
+                missing,
+                "in variants `Variant::Samples4`, `Variant::Samples8`: too many samples".to_owned()
+            ]
+        );
+    }
+
+    #[test]
+    fn omits_lookups_unless_every_variant_has_source() {
+        let with_source =
+            || -> Vec<syn::Item> { vec![syn::parse_quote! { pub const SOURCE: &str = ""; }] };
+        let without_source =
+            || -> Vec<syn::Item> { vec![syn::parse_quote! { compile_error!("failed"); }] };
+
+        let complete = Variant::combinations(&[axis("SHADOWS", "true, false")])
+            .into_iter()
+            .map(|variant| (variant, with_source()))
+            .collect();
+        let names = item_names(&variant_items(complete));
+        assert!(names.iter().any(|name| name == "Variant"));
+        assert!(names.iter().any(|name| name == "SOURCES"));
+
+        let incomplete = Variant::combinations(&[axis("SHADOWS", "true, false")])
+            .into_iter()
+            .zip([with_source(), without_source()])
+            .collect();
+        let names = item_names(&variant_items(incomplete));
+        assert_eq!(names, ["types", "shadows_true", "shadows_false"]);
+    }
+}
@@ -0,0 +1,9 @@
+#[include_wgsl_oil::include_wgsl_oil("shaders/aliases/main.wgsl")]
+mod aliased_shader {}
+
+#[test]
+fn binds_globals_of_aliased_structs() {
+    assert_eq!(aliased_shader::globals::scene_light::NAME, "scene_light");
+    assert_eq!(aliased_shader::globals::scene_light::binding::GROUP, 0);
+    assert_eq!(aliased_shader::globals::scene_light::binding::BINDING, 0);
+}
@@ -0,0 +1,7 @@
+#[include_wgsl_oil::include_wgsl_oil("shaders/embed/main.wgsl")]
+mod embed_shader {}
+
+#[test]
+fn imports_embedded_table_from_another_module() {
+    assert!(embed_shader::SOURCE.contains("7u"));
+}
@@ -0,0 +1,8 @@
+struct Light {
+    color: vec3<f32>,
+    intensity: f32,
+}
+
+fn brightness(light: Light) -> f32 {
+    return light.intensity;
+}
@@ -0,0 +1,13 @@
+#import light.wgsl::{Light as SceneLight, brightness as light_brightness}
+
+@group(0) @binding(0)
+var<uniform> scene_light: SceneLight;
+
+fn brightness() -> f32 {
+    return light_brightness(scene_light) * 2.0;
+}
+
+@compute @workgroup_size(1)
+fn main() {
+    let total = brightness();
+}
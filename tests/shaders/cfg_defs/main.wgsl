@@ -0,0 +1,7 @@
+#ifndef A
+#import uses_b.wgsl
+#endif
+
+fn main_colour() -> vec4<f32> {
+    return vec4<f32>(1.0);
+}
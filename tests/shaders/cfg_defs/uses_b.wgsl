@@ -0,0 +1,7 @@
+fn tint() -> f32 {
+#ifdef B
+    return 0.5;
+#else
+    return 1.0;
+#endif
+}
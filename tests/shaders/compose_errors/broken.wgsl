@@ -0,0 +1,3 @@
+fn main_fn() -> f32 {
+    return 1.0 +;
+}
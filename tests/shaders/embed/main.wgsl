@@ -0,0 +1,5 @@
+#import table.wgsl::TABLE
+
+fn last_entry() -> u32 {
+    return TABLE[3];
+}
@@ -0,0 +1 @@
+#embed "table.csv" as TABLE: array<u32, 4>
@@ -0,0 +1,3 @@
+fn wood_colour() -> vec3<f32> {
+    return vec3<f32>(1.0);
+}
@@ -0,0 +1,3 @@
+fn leaf_fn() -> f32 {
+    return 1.0;
+}
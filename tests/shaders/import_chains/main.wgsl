@@ -0,0 +1,5 @@
+#import middle.wgsl::middle_fn
+
+fn main_fn() -> f32 {
+    return middle_fn();
+}
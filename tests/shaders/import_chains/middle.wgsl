@@ -0,0 +1,6 @@
+// Passes the leaf through
+#import leaf.wgsl::leaf_fn
+
+fn middle_fn() -> f32 {
+    return leaf_fn();
+}
@@ -0,0 +1,6 @@
+// line 1
+#include "parts/first.wgsl"
+fn main_fn() {}
+#ifdef MISSING_PART
+#include "missing.wgsl"
+#endif
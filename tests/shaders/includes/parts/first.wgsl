@@ -0,0 +1,3 @@
+fn first() {}
+#include "second.wgsl"
+fn after_second() {}
@@ -0,0 +1,5 @@
+#define_import_path my_lib::lighting
+
+fn shade(intensity: f32) -> f32 {
+    return intensity * 0.5;
+}
@@ -0,0 +1,5 @@
+#import my_lib::lighting::shade
+
+fn main_fn() -> f32 {
+    return shade(1.0);
+}
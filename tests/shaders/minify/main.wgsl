@@ -0,0 +1,13 @@
+fn brighten(color: vec4<f32>) -> vec4<f32> {
+    return color * 2.0;
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    return vec4<f32>(f32(index), 0.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return brighten(vec4<f32>(1.0));
+}
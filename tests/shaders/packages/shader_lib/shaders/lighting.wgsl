@@ -0,0 +1,3 @@
+fn ambient() -> f32 {
+    return 0.1;
+}
@@ -0,0 +1,5 @@
+#import kernel.wgsl::weight
+
+fn blur() -> f32 {
+    return weight() * f32(#RADIUS);
+}
@@ -0,0 +1,7 @@
+fn weight() -> f32 {
+#ifdef HORIZONTAL
+    return 0.5;
+#else
+    return 0.25;
+#endif
+}
@@ -0,0 +1,6 @@
+#import blur.wgsl as Blur5 with (RADIUS = 5)
+#import blur.wgsl as Blur9 with (RADIUS = 9, HORIZONTAL)
+
+fn main_fn() -> f32 {
+    return Blur5::blur() + Blur9::blur();
+}
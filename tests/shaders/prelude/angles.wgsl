@@ -0,0 +1,5 @@
+#import consts.wgsl::{TAU}
+
+fn turns(x: f32) -> f32 {
+    return x * TAU;
+}
@@ -0,0 +1 @@
+const TAU: f32 = 6.2831855;
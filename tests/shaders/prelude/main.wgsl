@@ -0,0 +1,3 @@
+fn main_fn() -> f32 {
+    return square(2.0);
+}
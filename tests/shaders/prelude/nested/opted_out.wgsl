@@ -0,0 +1,4 @@
+#no_prelude
+fn half(x: f32) -> f32 {
+    return x / 2.0;
+}
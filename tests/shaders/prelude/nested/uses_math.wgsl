@@ -0,0 +1,3 @@
+fn cube(x: f32) -> f32 {
+    return square(x) * x;
+}
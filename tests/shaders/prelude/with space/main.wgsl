@@ -0,0 +1,3 @@
+fn main_fn() -> vec3<f32> {
+    return tint(vec3(1.0));
+}
@@ -0,0 +1,3 @@
+fn tint(color: vec3<f32>) -> vec3<f32> {
+    return color * 0.5;
+}
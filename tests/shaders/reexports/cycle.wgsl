@@ -0,0 +1,5 @@
+#export cycle_utils.wgsl::{cycle_utils_fn}
+
+fn cycle_fn() -> f32 {
+    return 1.0;
+}
@@ -0,0 +1,5 @@
+#import cycle.wgsl::cycle_fn
+
+fn cycle_utils_fn() -> f32 {
+    return cycle_fn();
+}
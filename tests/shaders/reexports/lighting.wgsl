@@ -0,0 +1,3 @@
+fn light(intensity: f32) -> f32 {
+    return intensity * 0.5;
+}
@@ -0,0 +1,5 @@
+#import prelude.wgsl::{helper, apply_light}
+
+fn main_fn() -> f32 {
+    return apply_light(helper());
+}
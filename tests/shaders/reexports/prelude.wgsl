@@ -0,0 +1,2 @@
+#export utils.wgsl::{helper}
+#export lighting.wgsl::{light as apply_light}
@@ -0,0 +1,7 @@
+fn common_fn() -> f32 {
+#ifdef QUALITY
+    return 2.0;
+#else
+    return 1.0;
+#endif
+}
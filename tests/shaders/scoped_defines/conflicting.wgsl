@@ -0,0 +1,6 @@
+#import quality.wgsl::quality_fn
+#import plain.wgsl::plain_fn
+
+fn main_fn() -> f32 {
+    return quality_fn() + plain_fn();
+}
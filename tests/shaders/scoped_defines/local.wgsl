@@ -0,0 +1,6 @@
+#import scaled.wgsl::scaled_fn
+#import plain.wgsl::plain_fn
+
+fn main_fn() -> f32 {
+    return scaled_fn() + plain_fn();
+}
@@ -0,0 +1,5 @@
+#import common.wgsl::common_fn
+
+fn plain_fn() -> f32 {
+    return common_fn();
+}
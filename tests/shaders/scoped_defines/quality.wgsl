@@ -0,0 +1,6 @@
+#define QUALITY 2
+#import common.wgsl::common_fn
+
+fn quality_fn() -> f32 {
+    return common_fn();
+}
@@ -0,0 +1,10 @@
+#define DOUBLED
+#import common.wgsl::common_fn
+
+fn scaled_fn() -> f32 {
+#ifdef DOUBLED
+    return common_fn() * 2.0;
+#else
+    return common_fn();
+#endif
+}
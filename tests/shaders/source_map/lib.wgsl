@@ -0,0 +1,4 @@
+// Returns the wrong type, which is only caught by validation
+fn broken() -> f32 {
+    return 1u;
+}
@@ -0,0 +1,5 @@
+#import lib.wgsl::broken
+
+fn main_fn() -> f32 {
+    return broken();
+}
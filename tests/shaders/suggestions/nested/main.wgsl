@@ -0,0 +1,5 @@
+#import utils.wgsl::helper
+
+fn main_fn() -> f32 {
+    return helper();
+}